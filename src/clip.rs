@@ -1,31 +1,533 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use clipboard::ClipboardContext;
 
+use config::{ClipboardBackend, Config};
+
+/// The most kills kept to paste again with yank-pop.
+const KILL_RING_SIZE: usize = 60;
+
+const REGISTERS_FILE: &'static str = ".mutxt_registers";
+
+const BASE64_CHARS: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_CHARS[(group >> (18 - 6 * i)) & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Sets the terminal's clipboard with OSC 52 escape sequences, which reach
+/// the terminal the user is sitting at even over SSH.
+pub struct Osc52 {
+    /// Whether to wrap the sequence so tmux passes it on to the terminal
+    tmux: bool,
+    /// The longest sequence to send, in bytes
+    limit: usize,
+}
+
+impl Osc52 {
+    pub fn new(tmux: bool, limit: usize) -> Self {
+        Osc52 { tmux: tmux, limit: limit }
+    }
+
+    /// Returns the escape sequence setting the clipboard to `text`, or
+    /// `None` if it would be longer than the limit.
+    fn sequence(&self, text: &str) -> Option<String> {
+        let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+        let sequence = if self.tmux {
+            // tmux passes on what is inside a DCS string with its escapes doubled
+            format!("\x1bPtmux;{}\x1b\\", sequence.replace("\x1b", "\x1b\x1b"))
+        } else {
+            sequence
+        };
+        if sequence.len() > self.limit {
+            None
+        } else {
+            Some(sequence)
+        }
+    }
+
+    /// Writes the sequence setting the clipboard to `text` to `out`,
+    /// returning false if the text is too long to send.
+    pub fn write_to<W: Write>(&self, out: &mut W, text: &str) -> io::Result<bool> {
+        match self.sequence(text) {
+            Some(sequence) => {
+                try!(out.write_all(sequence.as_bytes()));
+                try!(out.flush());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+/// Runs `command`, giving it `input` on its standard input, and returns what
+/// it printed. Returns `None` if it can't be run, fails, or doesn't finish
+/// within `timeout`, in which case it is killed.
+fn run_command(command: &[String], input: Option<&str>, timeout: Duration) -> Option<String> {
+    let mut child = match Command::new(&command[0]).args(&command[1..])
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        // Copy commands like xclip stay behind to serve the selection, and
+        // would hold a pipe on their output open
+        .stdout(if input.is_some() { Stdio::null() } else { Stdio::piped() })
+        .stderr(Stdio::null())
+        .spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            debug!("could not run {}: {}", command[0], e);
+            return None;
+        }
+    };
+    let deadline = Instant::now() + timeout;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let input = input.map(|input| input.to_owned());
+    // The pipes are handled on another thread, so a command that never reads
+    // or closes them can be given up on
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+        let mut output = String::new();
+        if let Some(mut stdout) = stdout {
+            let _ = stdout.read_to_string(&mut output);
+        }
+        let _ = sender.send(output);
+    });
+    let output = receiver.recv_timeout(timeout).ok();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return if status.success() { output } else { None },
+            Ok(None) if output.is_some() && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5));
+            },
+            _ => {
+                warn!("gave up waiting for {}", command[0]);
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            },
+        }
+    }
+}
+
+/// Returns whether `program` can be found on the `PATH`.
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").map_or(false, |paths| {
+        env::split_paths(&paths).any(|dir| dir.join(program).is_file())
+    })
+}
+
+/// A clipboard reached through external commands, such as `wl-copy` and
+/// `wl-paste`.
+pub struct CommandProvider {
+    /// The command given copied text on its input
+    copy: Vec<String>,
+    /// The command printing the text to paste, if there is one
+    paste: Option<Vec<String>>,
+    timeout: Duration,
+}
+
+impl CommandProvider {
+    fn new(copy: &[&str], paste: &[&str], timeout: Duration) -> Self {
+        CommandProvider {
+            copy: copy.iter().map(|arg| arg.to_string()).collect(),
+            paste: Some(paste.iter().map(|arg| arg.to_string()).collect()),
+            timeout: timeout,
+        }
+    }
+
+    /// Runs the given shell commands, leaving out pasting if `paste` is
+    /// empty.
+    fn shell(copy: &str, paste: &str, timeout: Duration) -> Self {
+        let shell = |command: &str| vec!["sh".to_owned(), "-c".to_owned(), command.to_owned()];
+        CommandProvider {
+            copy: shell(copy),
+            paste: if paste.is_empty() { None } else { Some(shell(paste)) },
+            timeout: timeout,
+        }
+    }
+
+    fn wl_clipboard(timeout: Duration) -> Self {
+        CommandProvider::new(&["wl-copy"], &["wl-paste", "--no-newline"], timeout)
+    }
+
+    fn xclip(timeout: Duration) -> Self {
+        CommandProvider::new(&["xclip", "-selection", "clipboard"],
+                             &["xclip", "-selection", "clipboard", "-out"], timeout)
+    }
+
+    fn pbcopy(timeout: Duration) -> Self {
+        CommandProvider::new(&["pbcopy"], &["pbpaste"], timeout)
+    }
+
+    pub fn set_contents(&self, s: &str) -> bool {
+        run_command(&self.copy, Some(s), self.timeout).is_some()
+    }
+
+    pub fn get_contents(&self) -> Option<String> {
+        self.paste.as_ref().and_then(|paste| run_command(paste, None, self.timeout))
+    }
+}
+
+/// Where copied text is sent besides the kill ring.
+enum Backend {
+    System(ClipboardContext),
+    Osc52(Osc52),
+    Command(CommandProvider),
+    None,
+}
+
+impl Backend {
+    /// Picks the backend the config asks for. Automatically, OSC 52 is
+    /// picked in SSH sessions, where the other clipboards would be the remote
+    /// machine's. Otherwise `wl-copy` is tried on Wayland, then `xclip` on X,
+    /// then `pbcopy`, then the system clipboard, and OSC 52 if none of them
+    /// are there.
+    fn from_config(config: &Config) -> Self {
+        let osc52 = Osc52::new(env::var_os("TMUX").is_some(), config.osc52_limit);
+        let timeout = Duration::from_millis(config.clipboard_timeout as u64);
+        match config.clipboard {
+            ClipboardBackend::Osc52 => Backend::Osc52(osc52),
+            ClipboardBackend::System => {
+                ClipboardContext::new().ok().map_or(Backend::None, Backend::System)
+            },
+            ClipboardBackend::WlClipboard => Backend::Command(CommandProvider::wl_clipboard(timeout)),
+            ClipboardBackend::Xclip => Backend::Command(CommandProvider::xclip(timeout)),
+            ClipboardBackend::Pbcopy => Backend::Command(CommandProvider::pbcopy(timeout)),
+            ClipboardBackend::Command => {
+                if config.clipboard_copy_command.is_empty() {
+                    warn!("clipboard is set to command, but clipboard_copy_command isn't set");
+                    return Backend::None;
+                }
+                Backend::Command(CommandProvider::shell(&config.clipboard_copy_command,
+                                                        &config.clipboard_paste_command,
+                                                        timeout))
+            },
+            ClipboardBackend::Auto => {
+                if env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some() {
+                    Backend::Osc52(osc52)
+                } else if env::var_os("WAYLAND_DISPLAY").is_some() && on_path("wl-copy") {
+                    Backend::Command(CommandProvider::wl_clipboard(timeout))
+                } else if env::var_os("DISPLAY").is_some() && on_path("xclip") {
+                    Backend::Command(CommandProvider::xclip(timeout))
+                } else if on_path("pbcopy") {
+                    Backend::Command(CommandProvider::pbcopy(timeout))
+                } else {
+                    ClipboardContext::new().ok().map_or(Backend::Osc52(osc52), Backend::System)
+                }
+            },
+        }
+    }
+}
+
+/// The system or terminal clipboard, with an Emacs-style ring of the text killed and
+/// copied, and named registers that are kept between sessions in
+/// `~/.mutxt_registers`.
 pub struct Clipboard {
-    real_clipboard: Option<ClipboardContext>,
-    contents: String,
+    backend: Backend,
+    /// Text killed and copied, oldest first. The newest is also sent to the
+    /// backend, and is what is pasted if it can't be read back.
+    kill_ring: Vec<String>,
+    /// How far back from the newest kill the last paste or yank-pop reached
+    yank_index: usize,
+    /// Snippets stored by name
+    registers: BTreeMap<String, String>,
+    /// Where the registers are saved
+    registers_path: Option<PathBuf>,
 }
 
 impl Clipboard {
-    pub fn new() -> Self {
-        Clipboard {
-            real_clipboard: ClipboardContext::new().ok(),
-            contents: String::new(),
+    pub fn new(config: &Config) -> Self {
+        let mut clipboard = Clipboard {
+            backend: Backend::from_config(config),
+            kill_ring: vec![],
+            yank_index: 0,
+            registers: BTreeMap::new(),
+            registers_path: env::var_os("HOME").map(|home| PathBuf::from(home).join(REGISTERS_FILE)),
+        };
+        if let Some(path) = clipboard.registers_path.clone() {
+            match clipboard.read_registers(&path) {
+                Ok(_) => debug!("loaded registers from {}", path.display()),
+                Err(e) => debug!("not loading registers from {}: {}", path.display(), e),
+            }
+        }
+        clipboard
+    }
+
+    fn push_kill(&mut self, s: String) {
+        if self.kill_ring.last() != Some(&s) {
+            self.kill_ring.push(s);
+        }
+        if self.kill_ring.len() > KILL_RING_SIZE {
+            self.kill_ring.remove(0);
+        }
+        self.yank_index = 0;
+    }
+
+    fn set_real_clipboard(&mut self) {
+        let s = match self.kill_ring.last() {
+            Some(s) => s.clone(),
+            None => return,
+        };
+        match self.backend {
+            Backend::System(ref mut clip) => {
+                // throw away result -- internal clipboard will fail over
+                let _ = clip.set_contents(s);
+            },
+            Backend::Osc52(ref osc52) => {
+                match osc52.write_to(&mut io::stdout(), &s) {
+                    Ok(true) => {},
+                    Ok(false) => debug!("not sending {} bytes over OSC 52", s.len()),
+                    Err(e) => warn!("could not send OSC 52 sequence: {}", e),
+                }
+            },
+            Backend::Command(ref provider) => {
+                // the internal clipboard will fail over
+                provider.set_contents(&s);
+            },
+            Backend::None => {},
         }
     }
 
     pub fn set<S: AsRef<str>>(&mut self, s: S) {
-        self.contents = s.as_ref().to_owned();
-        if let Some(clip) = self.real_clipboard.as_mut() {
-            // throw away result -- internal clipboard will fail over
-            let _ = clip.set_contents(s.as_ref().to_owned());
+        self.push_kill(s.as_ref().to_owned());
+        self.set_real_clipboard();
+    }
+
+    /// Adds killed text to the ring. When `continuing` a run of kills, the
+    /// text is joined onto the newest kill instead, before it if `backward`
+    /// (as when deleting backwards) and after it otherwise.
+    pub fn kill<S: AsRef<str>>(&mut self, s: S, backward: bool, continuing: bool) {
+        let s = s.as_ref();
+        match self.kill_ring.last_mut() {
+            Some(last) if continuing => {
+                if backward {
+                    last.insert_str(0, s);
+                } else {
+                    last.push_str(s);
+                }
+            },
+            _ => {
+                if s.is_empty() {
+                    return;
+                }
+                self.kill_ring.push(s.to_owned());
+            },
+        }
+        if self.kill_ring.len() > KILL_RING_SIZE {
+            self.kill_ring.remove(0);
+        }
+        self.yank_index = 0;
+        self.set_real_clipboard();
+    }
+
+    /// Returns the text to paste. Text copied from other programs is added
+    /// to the kill ring first, so yank-pop can go back past it.
+    pub fn get(&mut self) -> String {
+        // The terminal's clipboard can't be read back, only set
+        let real_contents = match self.backend {
+            Backend::System(ref clip) => clip.get_contents().ok(),
+            Backend::Command(ref provider) => provider.get_contents(),
+            _ => None,
+        };
+        if let Some(contents) = real_contents {
+            if !contents.is_empty() {
+                self.push_kill(contents);
+            }
+        }
+        self.yank_index = 0;
+        self.kill_ring.last().cloned().unwrap_or(String::new())
+    }
+
+    /// Steps back to the next older kill after a paste or yank-pop, wrapping
+    /// around to the newest after the oldest. Returns `None` if there is no
+    /// other kill to step to.
+    pub fn yank_pop(&mut self) -> Option<String> {
+        let len = self.kill_ring.len();
+        if len < 2 {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % len;
+        Some(self.kill_ring[len - 1 - self.yank_index].clone())
+    }
+
+    pub fn register(&self, name: &str) -> Option<&str> {
+        self.registers.get(name).map(|s| s.as_str())
+    }
+
+    /// Stores `s` in the register called `name`, saving the registers for
+    /// later sessions.
+    pub fn set_register<S: AsRef<str>>(&mut self, name: &str, s: S) {
+        self.registers.insert(name.to_owned(), s.as_ref().to_owned());
+        if let Some(path) = self.registers_path.clone() {
+            if let Err(e) = self.write_registers(&path) {
+                warn!("could not save registers to {}: {}", path.display(), e);
+            }
         }
     }
 
-    pub fn get(&self) -> String {
-        let internal_contents = self.contents.clone();
-        match self.real_clipboard.as_ref() {
-            Some(clip) => clip.get_contents().unwrap_or(internal_contents),
-            None => internal_contents,
+    /// Reads the registers saved as `name<TAB>text` lines, with the text's
+    /// newlines, tabs and backslashes escaped.
+    fn read_registers(&mut self, path: &PathBuf) -> io::Result<()> {
+        let file = try!(File::open(path));
+        for line in BufReader::new(file).lines() {
+            let line = try!(line);
+            let mut parts = line.splitn(2, '\t');
+            let name = parts.next().unwrap_or("");
+            if let Some(text) = parts.next() {
+                self.registers.insert(name.to_owned(), unescape(text));
+            }
         }
+        Ok(())
+    }
+
+    fn write_registers(&self, path: &PathBuf) -> io::Result<()> {
+        let mut file = try!(File::create(path));
+        for (name, text) in &self.registers {
+            try!(write!(file, "{}\t{}\n", name, escape(text)));
+        }
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+    use super::{base64, escape, unescape, run_command, Backend, Clipboard, CommandProvider, Osc52};
+
+    fn clipboard() -> Clipboard {
+        Clipboard {
+            backend: Backend::None,
+            kill_ring: vec![],
+            yank_index: 0,
+            registers: BTreeMap::new(),
+            registers_path: None,
+        }
+    }
+
+    #[test]
+    fn joins_successive_kills() {
+        let mut clip = clipboard();
+        clip.kill("world", true, false);
+        clip.kill("hello ", true, true);
+        clip.kill("!", false, true);
+        assert_eq!(clip.get(), "hello world!");
+        clip.kill("again", true, false);
+        assert_eq!(clip.get(), "again");
+    }
+
+    #[test]
+    fn yank_pop_cycles_through_older_kills() {
+        let mut clip = clipboard();
+        assert_eq!(clip.yank_pop(), None);
+        clip.set("one");
+        clip.kill("two", false, false);
+        clip.set("three");
+        assert_eq!(clip.get(), "three");
+        assert_eq!(clip.yank_pop(), Some("two".to_owned()));
+        assert_eq!(clip.yank_pop(), Some("one".to_owned()));
+        assert_eq!(clip.yank_pop(), Some("three".to_owned()));
+    }
+
+    #[test]
+    fn escapes_register_text() {
+        let text = "a\tb\\n\nc\r\n";
+        assert_eq!(escape(text), "a\\tb\\\\n\\nc\\r\\n");
+        assert_eq!(unescape(&escape(text)), text);
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("héllo\n".as_bytes()), "aMOpbGxvCg==");
+    }
+
+    #[test]
+    fn writes_osc52_sequences() {
+        let mut out = vec![];
+        assert!(Osc52::new(false, 100).write_to(&mut out, "hi").unwrap());
+        assert_eq!(out, b"\x1b]52;c;aGk=\x07");
+
+        let mut out = vec![];
+        assert!(Osc52::new(true, 100).write_to(&mut out, "hi").unwrap());
+        assert_eq!(out, b"\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\");
+
+        let mut out = vec![];
+        assert!(!Osc52::new(false, 11).write_to(&mut out, "hi").unwrap());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn runs_clipboard_commands() {
+        let timeout = Duration::from_millis(2000);
+        let provider = CommandProvider::shell("cat > /dev/null", "printf 'pasted text'", timeout);
+        assert!(provider.set_contents("copied text"));
+        assert_eq!(provider.get_contents(), Some("pasted text".to_owned()));
+
+        let provider = CommandProvider::shell("exit 1", "", timeout);
+        assert!(!provider.set_contents("copied text"));
+        assert_eq!(provider.get_contents(), None);
+
+        let command = vec!["no-such-clipboard-command".to_owned()];
+        assert_eq!(run_command(&command, None, timeout), None);
+    }
+
+    #[test]
+    fn gives_up_on_hung_commands() {
+        let start = Instant::now();
+        let command = vec!["sleep".to_owned(), "5".to_owned()];
+        assert_eq!(run_command(&command, Some("text"), Duration::from_millis(100)), None);
+        assert_eq!(run_command(&command, None, Duration::from_millis(100)), None);
+        assert!(start.elapsed() < Duration::from_millis(2000));
     }
 }
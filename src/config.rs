@@ -0,0 +1,226 @@
+use std::env;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::path::PathBuf;
+
+const CONFIG_FILE: &'static str = ".mutxtrc";
+
+/// The ways copied text can reach the clipboard.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClipboardBackend {
+    /// OSC 52 in SSH sessions, otherwise the first of the clipboard commands
+    /// or the system clipboard that is there, and OSC 52 if none are
+    Auto,
+    /// The system clipboard
+    System,
+    /// The terminal's clipboard, set with OSC 52 escape sequences
+    Osc52,
+    /// `wl-copy` and `wl-paste`, on Wayland
+    WlClipboard,
+    /// `xclip`, on X
+    Xclip,
+    /// `pbcopy` and `pbpaste`, on macOS
+    Pbcopy,
+    /// The commands set by `clipboard_copy_command` and
+    /// `clipboard_paste_command`
+    Command,
+}
+
+/// User-configurable editor settings, read from `~/.mutxtrc`.
+///
+/// The file is made of `key = value` lines; blank lines and lines starting
+/// with `#` are ignored.
+#[derive(Clone)]
+pub struct Config {
+    /// Whether long rows are wrapped onto several screen lines
+    pub soft_wrap: bool,
+    /// Whether soft wrapping breaks lines at word boundaries
+    pub wrap_at_words: bool,
+    /// Whether continuation lines of a wrapped row start with an indicator
+    pub wrap_indicator: bool,
+    /// The number of columns between tab stops
+    pub tab_width: usize,
+    /// Whether the Tab key inserts spaces instead of a tab character
+    pub expand_tabs: bool,
+    /// Whether a new line starts with the previous line's indentation
+    pub auto_indent: bool,
+    /// Whether lines are indented further after an opening bracket and
+    /// dedented when a closing bracket is typed
+    pub smart_indent: bool,
+    /// Whether typing an opening bracket or quote also types its closing
+    /// counterpart
+    pub auto_pair: bool,
+    /// Whether tabs, trailing spaces and non-breaking spaces are made visible
+    pub show_whitespace: bool,
+    /// Whether the background of the cursor's line is highlighted
+    pub highlight_current_line: bool,
+    /// The columns at which to draw vertical rulers
+    pub rulers: Vec<usize>,
+    /// Whether the bracket matching the one at the cursor is highlighted
+    pub highlight_matching_bracket: bool,
+    /// How many lines to keep between the cursor and the top or bottom of
+    /// the screen
+    pub scroll_margin: usize,
+    /// The column paragraphs are reflowed to fit within
+    pub fill_column: usize,
+    /// Whether the mouse can place the cursor, select and scroll, instead of
+    /// making the terminal's own selection
+    pub mouse: bool,
+    /// Where copied and cut text is sent
+    pub clipboard: ClipboardBackend,
+    /// The longest OSC 52 escape sequence to send, in bytes, since terminals
+    /// ignore or choke on longer ones
+    pub osc52_limit: usize,
+    /// The shell command given copied text on its input, when `clipboard` is
+    /// `command`
+    pub clipboard_copy_command: String,
+    /// The shell command printing the text to paste, when `clipboard` is
+    /// `command`
+    pub clipboard_paste_command: String,
+    /// How long to wait for a clipboard command before giving up on it, in
+    /// milliseconds
+    pub clipboard_timeout: usize,
+    /// The template for the left-aligned part of the status bar
+    pub status_left: String,
+    /// The template for the right-aligned part of the status bar
+    pub status_right: String,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            soft_wrap: false,
+            wrap_at_words: true,
+            wrap_indicator: true,
+            tab_width: 4,
+            expand_tabs: false,
+            auto_indent: true,
+            smart_indent: true,
+            auto_pair: false,
+            show_whitespace: false,
+            highlight_current_line: false,
+            rulers: vec![],
+            highlight_matching_bracket: true,
+            scroll_margin: 0,
+            fill_column: 72,
+            mouse: true,
+            clipboard: ClipboardBackend::Auto,
+            osc52_limit: 100000,
+            clipboard_copy_command: String::new(),
+            clipboard_paste_command: String::new(),
+            clipboard_timeout: 1000,
+            status_left: "{filename}{modified} - {lines} lines".to_owned(),
+            status_right: "{selection} {branch} {filetype} {line}:{column} {percent}".to_owned(),
+        }
+    }
+
+    /// Loads the configuration file from the user's home directory, falling
+    /// back to the defaults if there is none.
+    pub fn load() -> Self {
+        let mut config = Config::new();
+        if let Some(path) = config_path() {
+            match config.read_file(&path) {
+                Ok(_) => debug!("loaded config from {}", path.display()),
+                Err(e) => debug!("not loading config from {}: {}", path.display(), e),
+            }
+        }
+        config
+    }
+
+    fn read_file(&mut self, path: &PathBuf) -> io::Result<()> {
+        let file = try!(File::open(path));
+        for line in BufReader::new(file).lines() {
+            let line = try!(line);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+            if let Err(msg) = self.set(key, value) {
+                warn!("config: {}", msg);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a single option by name.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "soft_wrap" => self.soft_wrap = try!(parse_bool(key, value)),
+            "wrap_at_words" => self.wrap_at_words = try!(parse_bool(key, value)),
+            "wrap_indicator" => self.wrap_indicator = try!(parse_bool(key, value)),
+            "tab_width" => self.tab_width = try!(parse_usize(key, value)),
+            "expand_tabs" => self.expand_tabs = try!(parse_bool(key, value)),
+            "auto_indent" => self.auto_indent = try!(parse_bool(key, value)),
+            "smart_indent" => self.smart_indent = try!(parse_bool(key, value)),
+            "auto_pair" => self.auto_pair = try!(parse_bool(key, value)),
+            "show_whitespace" => self.show_whitespace = try!(parse_bool(key, value)),
+            "highlight_current_line" => self.highlight_current_line = try!(parse_bool(key, value)),
+            "rulers" => self.rulers = try!(parse_usize_list(key, value)),
+            "highlight_matching_bracket" => self.highlight_matching_bracket = try!(parse_bool(key, value)),
+            "scroll_margin" => self.scroll_margin = try!(parse_usize(key, value)),
+            "fill_column" => self.fill_column = try!(parse_usize(key, value)),
+            "mouse" => self.mouse = try!(parse_bool(key, value)),
+            "clipboard" => self.clipboard = try!(parse_clipboard(key, value)),
+            "osc52_limit" => self.osc52_limit = try!(parse_usize(key, value)),
+            "clipboard_copy_command" => self.clipboard_copy_command = parse_string(value),
+            "clipboard_paste_command" => self.clipboard_paste_command = parse_string(value),
+            "clipboard_timeout" => self.clipboard_timeout = try!(parse_usize(key, value)),
+            "status_left" => self.status_left = parse_string(value),
+            "status_right" => self.status_right = parse_string(value),
+            _ => return Err(format!("unknown option '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(CONFIG_FILE))
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("expected a boolean for '{}', got '{}'", key, value)),
+    }
+}
+
+fn parse_usize(key: &str, value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("expected a number for '{}', got '{}'", key, value))
+}
+
+fn parse_usize_list(key: &str, value: &str) -> Result<Vec<usize>, String> {
+    value.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| parse_usize(key, v))
+        .collect()
+}
+
+fn parse_clipboard(key: &str, value: &str) -> Result<ClipboardBackend, String> {
+    match value {
+        "auto" => Ok(ClipboardBackend::Auto),
+        "system" => Ok(ClipboardBackend::System),
+        "osc52" => Ok(ClipboardBackend::Osc52),
+        "wl-clipboard" => Ok(ClipboardBackend::WlClipboard),
+        "xclip" => Ok(ClipboardBackend::Xclip),
+        "pbcopy" => Ok(ClipboardBackend::Pbcopy),
+        "command" => Ok(ClipboardBackend::Command),
+        _ => Err(format!("expected auto, system, osc52, wl-clipboard, xclip, pbcopy or command \
+                          for '{}', got '{}'", key, value)),
+    }
+}
+
+/// Strips the quotes from a string value, which are needed to keep leading or
+/// trailing spaces.
+fn parse_string(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        value[1..value.len()-1].to_owned()
+    } else {
+        value.to_owned()
+    }
+}
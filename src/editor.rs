@@ -1,22 +1,31 @@
 use std::io;
 use std::io::{Write};
+use std::cmp;
 use std::iter;
+use std::collections::{HashMap, VecDeque};
 use std::io::{BufRead, BufReader};
 use std::fs::{File, OpenOptions};
-use std::collections::{HashSet};
 use termion::{cursor, clear, color, style};
 
+use config::Config;
+use search::{Search, SearchOptions};
+use status;
+use syntax;
+use words;
+use syntax::SyntaxHighlightRule;
+use transform;
+use transform::Case;
+
 const TAB: char = '\t';
-const SPACES_PER_TAB: usize = 4;
+const NBSP: char = '\u{a0}';
+const WRAP_INDICATOR: char = '↪';
+const BRACKET_PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+/// How many rows to look through for a matching bracket
+const BRACKET_SEARCH_ROWS: usize = 5000;
+/// How many edits can be undone
+const UNDO_LIMIT: usize = 500;
 const VERSION : &'static str = env!("CARGO_PKG_VERSION");
 
-bitflags! {
-    pub flags HighlightParams: u8 {
-        const HighlightStrings = (1 << 0),
-        const HighlightNumbers = (1 << 1),
-    }
-}
-
 #[derive(Copy, Clone)]
 pub enum HighlightType {
     Normal,
@@ -26,20 +35,17 @@ pub enum HighlightType {
     String,
     Number,
     Selection,
+    Whitespace,
+    MatchingBracket,
+    Cursor,
 }
 
-/// A type defining elements of syntax
-pub struct SyntaxHighlightRule {
-    /// The keywords of the language
-    keywords: HashSet<String>,
-    /// The start character sequence for a single-line comment
-    single_line_comment_start: HashSet<String>,
-    /// The start character sequence for a multi-line comment
-    multi_line_comment_start: String,
-    /// The end character sequence for a multi-line comment
-    multi_line_comment_end: String,
-    /// Flags specifying what elements of syntax should be highlighted
-    params: HighlightParams,
+/// Backgrounds drawn behind the text to mark out the cursor's surroundings.
+#[derive(Copy, Clone, PartialEq)]
+enum Decoration {
+    None,
+    CurrentLine,
+    Ruler,
 }
 
 pub struct Row {
@@ -71,15 +77,113 @@ impl Row {
         }
     }
 
-    pub fn render(&self) -> RenderedRow {
-        self.content.chars()
-            .flat_map(|ch| {
-                if ch == TAB {
-                    iter::repeat((' ', HighlightType::Normal)).take(SPACES_PER_TAB)
+    pub fn render(&self, tab_width: usize, show_whitespace: bool) -> RenderedRow {
+        use self::HighlightType::*;
+        let trailing = self.trailing_whitespace_start();
+        let mut rendered = RenderedRow::new();
+        for (idx, ch) in self.content.chars().enumerate() {
+            if ch == TAB {
+                // Pad out to the next tab stop
+                let width = tab_width - rendered.len() % tab_width;
+                if show_whitespace {
+                    rendered.push(('»', Whitespace));
+                    rendered.extend(iter::repeat((' ', Whitespace)).take(width - 1));
                 } else {
-                    iter::repeat((ch, HighlightType::Normal)).take(1)
+                    rendered.extend(iter::repeat((' ', Normal)).take(width));
                 }
-            }).collect()
+            } else if is_ascii_control(ch) {
+                // Caret notation, e.g. ^A for 0x01 and ^? for DEL
+                rendered.push(('^', NonPrint));
+                rendered.push((((ch as u8) ^ 0x40) as char, NonPrint));
+            } else if ch.is_control() {
+                rendered.push(('\u{fffd}', NonPrint));
+            } else if show_whitespace && ch == ' ' && idx >= trailing {
+                rendered.push(('·', Whitespace));
+            } else if show_whitespace && ch == NBSP {
+                rendered.push(('␣', Whitespace));
+            } else {
+                rendered.push((ch, Normal));
+            }
+        }
+        rendered
+    }
+
+    /// Returns the rendered column of every character in the row, followed by
+    /// the rendered width of the whole row.
+    pub fn render_cols(&self, tab_width: usize) -> Vec<usize> {
+        let mut cols = Vec::with_capacity(self.content.len() + 1);
+        let mut col = 0;
+        for ch in self.content.chars() {
+            cols.push(col);
+            if ch == TAB {
+                col += tab_width - col % tab_width;
+            } else if is_ascii_control(ch) {
+                col += 2;
+            } else {
+                col += 1;
+            }
+        }
+        cols.push(col);
+        cols
+    }
+
+    /// Returns the rendered column of the character at `col_idx`.
+    pub fn render_col(&self, col_idx: usize, tab_width: usize) -> usize {
+        let cols = self.render_cols(tab_width);
+        cols[cmp::min(col_idx, cols.len() - 1)]
+    }
+
+    /// Returns the column of the character displayed at rendered column `x`,
+    /// or the end of the row if it is shorter than that.
+    pub fn col_at(&self, x: usize, tab_width: usize) -> usize {
+        self.render_cols(tab_width).iter().rposition(|&col| col <= x).unwrap_or(0)
+    }
+
+    /// Replaces a tab spanning rendered column `x` with spaces, so that a
+    /// character starts there.
+    pub fn split_tab_at(&mut self, x: usize, tab_width: usize) {
+        let cols = self.render_cols(tab_width);
+        if let Some(idx) = (0..self.len()).find(|&i| cols[i] < x && x < cols[i + 1]) {
+            if self.content.chars().nth(idx) == Some(TAB) {
+                let byte = self.byte_index(idx);
+                let spaces: String = iter::repeat(' ').take(cols[idx + 1] - cols[idx]).collect();
+                self.content = format!("{}{}{}", &self.content[..byte], spaces, &self.content[byte + 1..]);
+            }
+        }
+    }
+
+    /// Pads the row with spaces until it reaches rendered column `x`.
+    pub fn pad_to(&mut self, x: usize, tab_width: usize) {
+        let width = self.render_col(self.len(), tab_width);
+        if width < x {
+            self.content.extend(iter::repeat(' ').take(x - width));
+        }
+    }
+
+    /// Returns the indices of the characters starting each screen line when
+    /// the row is wrapped to `width` columns. Continuation lines are `indent`
+    /// columns narrower than the first one.
+    pub fn wrap_points(&self, width: usize, indent: usize, at_words: bool, tab_width: usize) -> Vec<usize> {
+        let cols = self.render_cols(tab_width);
+        let mut points = vec![0];
+        let mut line_width = width;
+        let mut last_break = None;
+        for (idx, ch) in self.content.chars().enumerate() {
+            let start = points[points.len()-1];
+            if cols[idx + 1] - cols[start] > line_width && idx > start {
+                let new_start = match last_break {
+                    Some(b) if at_words && b > start => b,
+                    _ => idx,
+                };
+                points.push(new_start);
+                line_width = if width > indent + 1 { width - indent } else { 1 };
+                last_break = None;
+            }
+            if ch == ' ' {
+                last_break = Some(idx + 1);
+            }
+        }
+        points
     }
 
     pub fn push_str(&mut self, s: String) {
@@ -87,21 +191,47 @@ impl Row {
     }
 
     pub fn backspace(&mut self, at: usize) {
-        self.content.remove(at-1);
+        let idx = self.byte_index(at-1);
+        self.content.remove(idx);
     }
 
     pub fn insert_char(&mut self, at: usize, c: char) {
-        self.content.insert(at, c);
+        let idx = self.byte_index(at);
+        self.content.insert(idx, c);
+    }
+
+    /// Drops everything from column `col_idx` onwards.
+    pub fn truncate(&mut self, col_idx: usize) {
+        let idx = self.byte_index(col_idx);
+        self.content.truncate(idx);
+    }
+
+    /// Returns the length of the row in characters.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.content.chars().count()
+    }
+
+    /// Returns the byte offset of the character at column `col_idx`.
+    pub fn byte_index(&self, col_idx: usize) -> usize {
+        self.content.char_indices()
+            .nth(col_idx)
+            .map_or(self.content.len(), |(idx, _)| idx)
+    }
+
+    /// Returns the column at which the row's trailing whitespace starts.
+    pub fn trailing_whitespace_start(&self) -> usize {
+        self.len() - self.content.chars().rev().take_while(|c| c.is_whitespace()).count()
     }
 
     #[inline(always)]
     pub fn column_exists(&self, col_idx: usize) -> bool {
-        col_idx < self.content.len()
+        col_idx < self.len()
     }
 
     #[inline(always)]
     pub fn is_end(&self, col_idx: usize) -> bool {
-        col_idx == self.content.len()
+        col_idx == self.len()
     }
 }
 
@@ -115,6 +245,9 @@ pub struct Editor {
     row_offset: usize,
     /// The column offset into the file.
     col_offset: usize,
+    /// The number of screen lines of the row at `row_offset` scrolled off the
+    /// top of the screen when soft wrapping.
+    wrap_offset: usize,
     /// The number of rows able to be displayed on screen
     screen_rows: usize,
     /// The number of columns able to be displayed on screen
@@ -129,6 +262,111 @@ pub struct Editor {
     status_message: Option<String>,
     /// The syntax highlighting rule configured.
     syntax_highlight: Option<SyntaxHighlightRule>,
+    /// The user's settings
+    config: Config,
+    /// The line ending used by the file
+    line_ending: LineEnding,
+    /// The git branch the file is on
+    git_branch: Option<String>,
+    /// Where the selection was started, if one is being made. The selection
+    /// runs from here to the cursor.
+    selection_anchor: Option<(usize, usize)>,
+    /// The positions of the cursors besides the main one, which typing and
+    /// cursor movements also apply at
+    extra_cursors: Vec<(usize, usize)>,
+    /// Where a block selection was started, as a row and rendered column. The
+    /// block runs from here to the cursor's row and `block_x`.
+    block_anchor: Option<(usize, usize)>,
+    /// The rendered column the block selection reaches on the cursor's side,
+    /// which may be past the end of the cursor's row
+    block_x: usize,
+    /// The last block of text copied, so it can be pasted as a block again
+    copied_block: Option<String>,
+    /// Where the text last pasted at a single cursor starts and ends, so it
+    /// can be swapped for an older kill
+    pasted: Option<((usize, usize), (usize, usize))>,
+    /// The words offered to complete the one before the cursor, while the
+    /// completion popup is open
+    completion: Option<Completion>,
+    /// The edits to undo, most recent last
+    undo_stack: VecDeque<Change>,
+    /// The edits undone, to redo
+    redo_stack: Vec<Change>,
+    /// The rows changed so far by the edit in progress
+    pending: Option<PendingChange>,
+    /// The kind of the last edit and where it left the cursor
+    last_edit: Option<(EditKind, (usize, usize))>,
+    /// How many edits are in progress, so nested ones are undone as one
+    edit_depth: usize,
+}
+
+/// The rows an edit replaced, so it can be undone and redone.
+struct Change {
+    /// The first row changed
+    start: usize,
+    /// The rows from `start` before the edit
+    old_rows: Vec<String>,
+    /// The rows from `start` after the edit
+    new_rows: Vec<String>,
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize),
+}
+
+/// The rows changed by an edit in progress, which grow as `touch_rows` is
+/// told about more of them.
+struct PendingChange {
+    /// Whether any rows have been changed yet
+    dirty: bool,
+    /// The first row changed
+    start: usize,
+    /// The changed rows, as they were before the edit
+    old_rows: Vec<String>,
+    /// The number of rows after the changed ones
+    tail: usize,
+    cursor_before: (usize, usize),
+}
+
+/// The most completions shown in the popup at once.
+const COMPLETION_ROWS: usize = 8;
+
+/// The words offered to complete the one being typed.
+struct Completion {
+    /// The part of the word before the cursor
+    prefix: String,
+    /// The words starting with the prefix, best first
+    candidates: Vec<String>,
+    /// The index of the highlighted candidate
+    selected: usize,
+}
+
+/// The kinds of edits, so runs of typing or deleting are undone together.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -139,6 +377,124 @@ pub enum CursorDirection {
     Right,
 }
 
+/// Writes a single rendered character with its highlighting.
+fn render_cell<W: Write>(out: &mut W, c: char, hl: HighlightType, decoration: Decoration) -> io::Result<()> {
+    // TODO use configurable colors
+    match decoration {
+        Decoration::None => try!(write!(out, "{}", color::Bg(color::Reset))),
+        Decoration::CurrentLine => try!(write!(out, "{}", color::Bg(color::AnsiValue::grayscale(3)))),
+        Decoration::Ruler => try!(write!(out, "{}", color::Bg(color::AnsiValue::grayscale(7)))),
+    }
+    use self::HighlightType::*;
+    match hl {
+        NonPrint => {
+            try!(write!(out, "{}{}{}{}", color::Fg(color::Reset),
+                        style::Invert, c, style::NoInvert));
+        },
+        Normal => {
+            try!(write!(out, "{}{}", color::Fg(color::White), c));
+        },
+        Comment => {
+            try!(write!(out, "{}{}", color::Fg(color::Cyan), c));
+        },
+        Keyword => {
+            try!(write!(out, "{}{}", color::Fg(color::Magenta), c));
+        },
+        String => {
+            try!(write!(out, "{}{}", color::Fg(color::Green), c));
+        },
+        Number => {
+            try!(write!(out, "{}{}", color::Fg(color::Blue), c));
+        },
+        Selection => {
+            try!(write!(out, "{}{}{}", color::Bg(color::LightBlack),
+                        color::Fg(color::White), c));
+        },
+        Whitespace => {
+            try!(write!(out, "{}{}", color::Fg(color::LightBlack), c));
+        },
+        MatchingBracket => {
+            try!(write!(out, "{}{}{}", color::Bg(color::Blue),
+                        color::Fg(color::LightWhite), c));
+        },
+        Cursor => {
+            try!(write!(out, "{}{}{}{}", color::Fg(color::White),
+                        style::Invert, c, style::NoInvert));
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `ch` is an ASCII control character other than tab, which
+/// is displayed in caret notation.
+fn is_ascii_control(ch: char) -> bool {
+    ch != TAB && ((ch as u32) < 0x20 || ch == '\x7f')
+}
+
+/// Returns the index of the screen line holding column `col` of a wrapped
+/// row, given the row's wrap points.
+fn wrap_segment(points: &[usize], col: usize) -> usize {
+    points.iter().rposition(|&p| p <= col).unwrap_or(0)
+}
+
+/// Returns the spaces and tabs at the start of `text`.
+fn leading_whitespace(text: &str) -> &str {
+    let len = text.chars().take_while(|&c| c == ' ' || c == TAB).count();
+    &text[..len]
+}
+
+/// Removes `token` and a space following it from after the indentation of
+/// `content`, returning the column it was at and how many characters were
+/// removed.
+fn uncomment_start(content: &mut String, token: &str) -> (usize, usize) {
+    let indent = leading_whitespace(content).len();
+    let mut removed = token.len();
+    if content[indent + removed..].starts_with(' ') {
+        removed += 1;
+    }
+    content.drain(indent..indent + removed);
+    (indent, token.chars().count() + removed - token.len())
+}
+
+/// Returns the length in bytes of the indentation and comment marker at the
+/// start of `text`, along with any space after the marker. Doubled markers and
+/// doc comment markers like `///` and `//!` count as one.
+fn line_prefix_len(text: &str, markers: &[String]) -> usize {
+    let mut len = leading_whitespace(text).len();
+    let marker = markers.iter()
+        .filter(|marker| !marker.is_empty() && text[len..].starts_with(marker.as_str()))
+        .max_by_key(|marker| marker.len());
+    if let Some(marker) = marker {
+        len += marker.len();
+        let last = marker.chars().last().unwrap();
+        len += text[len..].chars().take_while(|&c| c == last).map(|c| c.len_utf8()).sum::<usize>();
+        if text[len..].starts_with('!') {
+            len += 1;
+        }
+        len += text[len..].chars().take_while(|&c| c == ' ' || c == TAB).count();
+    }
+    len
+}
+
+/// Returns the length in bytes of a list bullet such as `-`, `*` or `1.` at
+/// the start of `text`, along with the spaces after it.
+fn bullet_len(text: &str) -> usize {
+    let digits = text.chars().take_while(|c| c.is_digit(10)).count();
+    let marker = match text[digits..].chars().next() {
+        Some('-') | Some('*') | Some('+') if digits == 0 => 1,
+        Some('.') | Some(')') if digits > 0 => digits + 1,
+        _ => return 0,
+    };
+    let spaces = text[marker..].chars().take_while(|&c| c == ' ').count();
+    if spaces == 0 { 0 } else { marker + spaces }
+}
+
+/// Returns the byte offset of the character after the one at `offset` in
+/// `text`, or one past the end if `offset` is already at the end.
+fn next_char_offset(text: &str, offset: usize) -> usize {
+    offset + text[offset..].chars().next().map_or(1, |c| c.len_utf8())
+}
+
 impl Editor {
     pub fn new(screen_rows: usize, screen_cols: usize) -> Self {
         Editor {
@@ -146,6 +502,7 @@ impl Editor {
             cursor_y: 0,
             row_offset: 0,
             col_offset: 0,
+            wrap_offset: 0,
             screen_rows: screen_rows-3,
             screen_cols: screen_cols,
             rows: vec![],
@@ -153,124 +510,342 @@ impl Editor {
             filename: None,
             status_message: None,
             syntax_highlight: None,
+            config: Config::new(),
+            line_ending: LineEnding::Lf,
+            git_branch: None,
+            selection_anchor: None,
+            extra_cursors: vec![],
+            block_anchor: None,
+            block_x: 0,
+            copied_block: None,
+            pasted: None,
+            completion: None,
+            undo_stack: VecDeque::new(),
+            redo_stack: vec![],
+            pending: None,
+            last_edit: None,
+            edit_depth: 0,
         }
     }
 
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.scroll();
+    }
+
     pub fn set_screen_size(&mut self, screen_rows: usize, screen_cols: usize) {
         self.screen_rows = screen_rows - 3;
         self.screen_cols = screen_cols;
+        self.scroll();
     }
 
-    pub fn render<W: Write>(&self, out: &mut W) -> io::Result<()> {
-        try!(write!(out, "{}{}", cursor::Hide, cursor::Goto(1, 1)));
-        // now we render the text, line by line
+    /// Turns soft wrapping on or off, returning whether it is now enabled.
+    pub fn toggle_soft_wrap(&mut self) -> bool {
+        self.config.soft_wrap = !self.config.soft_wrap;
+        self.scroll();
+        self.config.soft_wrap
+    }
+
+    /// Turns expanding tabs to spaces on or off, returning whether it is now
+    /// enabled.
+    pub fn toggle_expand_tabs(&mut self) -> bool {
+        self.config.expand_tabs = !self.config.expand_tabs;
+        self.config.expand_tabs
+    }
+
+    /// Turns showing tabs and trailing spaces on or off, returning whether it
+    /// is now enabled.
+    pub fn toggle_show_whitespace(&mut self) -> bool {
+        self.config.show_whitespace = !self.config.show_whitespace;
+        self.config.show_whitespace
+    }
+
+    fn render_filler<W: Write>(&self, out: &mut W, y: usize) -> io::Result<()> {
+        if self.rows.len() == 0 && y == self.screen_rows / 3 {
+            let title = format!("mutxt version {}", VERSION);
+            let padding = self.screen_cols.saturating_sub(title.len())/2;
+            let padding_str: String = iter::repeat(' ').take(padding.saturating_sub(1)).collect();
+            try!(write!(out, "~{}{}{}{}{}\r\n", padding_str, style::Bold, title, style::Reset,
+                        clear::AfterCursor));
+        } else {
+            try!(write!(out, "~{}\r\n", clear::AfterCursor));
+        }
+        Ok(())
+    }
+
+    /// Writes one screen line's worth of row `file_row`, whose first cell is at
+    /// rendered column `start_col` of the row, decorating the cursor line,
+    /// selection, rulers and the brackets at `brackets`.
+    fn render_line<W: Write>(&self, out: &mut W, file_row: usize, cells: &[(char, HighlightType)],
+                             start_col: usize, width: usize, brackets: &[(usize, usize)]) -> io::Result<()> {
+        let current_line = self.config.highlight_current_line && file_row == self.cursor_position().0;
+        let selected = self.selection_cols(file_row).unwrap_or((0, 0));
+        let cursors = self.extra_cursor_cols(file_row);
+        // Pad the line out so the whole cursor line, selection, extra cursors
+        // and any rulers are drawn
+        let mut len = cmp::min(cells.len(), width);
+        if current_line {
+            len = width;
+        }
+        if selected.1 > start_col {
+            len = cmp::max(len, cmp::min(selected.1 - start_col, width));
+        }
+        for &col in &cursors {
+            if col >= start_col && col - start_col < width {
+                len = cmp::max(len, col - start_col + 1);
+            }
+        }
+        for &ruler in &self.config.rulers {
+            if ruler > start_col && ruler - start_col <= width {
+                len = cmp::max(len, ruler - start_col);
+            }
+        }
+        for x in 0..len {
+            let col = start_col + x;
+            let (c, mut hl) = cells.get(x).cloned().unwrap_or((' ', HighlightType::Normal));
+            if cursors.contains(&col) {
+                hl = HighlightType::Cursor;
+            } else if brackets.contains(&(file_row, col)) {
+                hl = HighlightType::MatchingBracket;
+            } else if col >= selected.0 && col < selected.1 {
+                hl = HighlightType::Selection;
+            }
+            let decoration = if self.config.rulers.contains(&(col + 1)) {
+                Decoration::Ruler
+            } else if current_line {
+                Decoration::CurrentLine
+            } else {
+                Decoration::None
+            };
+            try!(render_cell(out, c, hl, decoration));
+        }
+        write!(out, "{}{}{}\r\n", color::Fg(color::White), color::Bg(color::Reset), clear::AfterCursor)
+    }
+
+    /// Renders the rows scrolled horizontally by `col_offset`, returning the
+    /// screen position of the cursor.
+    fn render_rows<W: Write>(&self, out: &mut W) -> io::Result<(usize, usize)> {
+        let brackets = self.bracket_highlights();
         for y in 0..self.screen_rows {
             let file_row = self.row_offset + y;
 
             if file_row >= self.rows.len() {
-                if self.rows.len() == 0 && y == self.screen_rows / 3 {
-                    let title = format!("mutxt version {}{}{}\r\n", style::Bold, VERSION, style::Reset);
-                    let padding = (self.screen_cols - title.len())/2;
-                    let padding_str: String = iter::repeat(' ').take(padding-1).collect();
-                    try!(write!(out, "~{}{}", padding_str, title));
-                } else {
-                    try!(write!(out, "~{}\r\n", clear::AfterCursor));
+                try!(self.render_filler(out, y));
+                continue;
+            }
+
+            let rendered_row = self.rows[file_row].render(self.tab_width(), self.config.show_whitespace);
+            let skip = cmp::min(self.rows[file_row].render_col(self.col_offset, self.tab_width()),
+                                rendered_row.len());
+            try!(self.render_line(out, file_row, &rendered_row[skip..], skip,
+                                  self.screen_cols, &brackets));
+        }
+
+        // Put the cursor in the right spot.
+        let mut cx = 1;
+        let (file_row, file_col) = self.cursor_position();
+        if let Some(row) = self.rows.get(file_row) {
+            let cols = row.render_cols(self.tab_width());
+            cx += cols[file_col] - cols[self.col_offset];
+        }
+        Ok((cx, self.cursor_y + 1))
+    }
+
+    /// Renders the rows wrapped onto as many screen lines as they need,
+    /// returning the screen position of the cursor.
+    fn render_wrapped_rows<W: Write>(&self, out: &mut W) -> io::Result<(usize, usize)> {
+        let (cursor_row, cursor_col) = self.cursor_position();
+        let indicator_width = self.wrap_indicator_width();
+        let brackets = self.bracket_highlights();
+        let mut cursor = (1, 1);
+        let mut file_row = self.row_offset;
+        let mut segment = self.wrap_offset;
+        let mut y = 0;
+        while y < self.screen_rows {
+            if file_row >= self.rows.len() {
+                if file_row == cursor_row {
+                    cursor = (1, y + 1);
                 }
+                try!(self.render_filler(out, y));
+                file_row += 1;
+                y += 1;
                 continue;
             }
 
-            let rendered_row = self.rows[file_row].render();
-            let mut len = rendered_row.len() - self.col_offset;
-            if len > 0 {
-                if len > self.screen_cols {
-                    len = self.screen_cols;
+            let row = &self.rows[file_row];
+            let rendered_row = row.render(self.tab_width(), self.config.show_whitespace);
+            let cols = row.render_cols(self.tab_width());
+            let points = self.wrap_points(row);
+            let len = cols.len() - 1;
+            while segment < points.len() && y < self.screen_rows {
+                let start = points[segment];
+                let end = points.get(segment + 1).cloned().unwrap_or(len);
+                let indent = if segment > 0 { indicator_width } else { 0 };
+                if indent > 0 {
+                    try!(write!(out, "{}{}", color::Fg(color::LightBlack), WRAP_INDICATOR));
                 }
-                for (c, hl) in rendered_row.into_iter().skip(self.col_offset).take(len) {
-                    // TODO use configurable colors
-                    use self::HighlightType::*;
-                    match hl {
-                        NonPrint => {
-                            try!(write!(out, "{}{}{}", color::Fg(color::Reset),
-                                        color::Bg(color::Reset), c));
-                        },
-                        Normal => {
-                            try!(write!(out, "{}{}", color::Fg(color::White), c));
-                        },
-                        Comment => {
-                            try!(write!(out, "{}{}", color::Fg(color::Cyan), c));
-                        },
-                        Keyword => {
-                            try!(write!(out, "{}{}", color::Fg(color::Magenta), c));
-                        },
-                        String => {
-                            try!(write!(out, "{}{}", color::Fg(color::Green), c));
-                        },
-                        Number => {
-                            try!(write!(out, "{}{}", color::Fg(color::Blue), c));
-                        },
-                        Selection => {
-                            try!(write!(out, "{}{}{}", color::Bg(color::LightBlack),
-                                        color::Fg(color::White), c));
-                        }
-                    }
+                let cell_start = cols[start];
+                let cell_end = cols[end];
+                try!(self.render_line(out, file_row, &rendered_row[cell_start..cell_end], cell_start,
+                                      self.screen_cols - indent, &brackets));
+
+                let last_segment = segment + 1 == points.len();
+                if file_row == cursor_row && cursor_col >= start && (cursor_col < end || last_segment) {
+                    let x = indent + cols[cursor_col] - cell_start;
+                    cursor = (cmp::min(x, self.screen_cols - 1) + 1, y + 1);
                 }
+                segment += 1;
+                y += 1;
             }
-            try!(write!(out, "{}{}\r\n", color::Fg(color::White), clear::AfterCursor));
+            segment = 0;
+            file_row += 1;
         }
-        // Render status bar
-        try!(write!(out, "{}{}", clear::AfterCursor, style::Invert));
-        let modified_str = if self.modified {
-            " (modified)"
+        Ok(cursor)
+    }
+
+    pub fn render<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        try!(write!(out, "{}{}", cursor::Hide, cursor::Goto(1, 1)));
+        // now we render the text, line by line
+        let (cx, cy) = if self.config.soft_wrap {
+            try!(self.render_wrapped_rows(out))
         } else {
-            ""
+            try!(self.render_rows(out))
         };
-        let filename_str = match self.filename {
-            Some(ref f) => &f,
-            None => "(no file)"
-        };
-        let lhs_status = format!("{}{} - {} lines", filename_str, modified_str, self.rows.len());
-        let rhs_status = format!("{}/{}", self.row_offset+self.cursor_y+1, self.rows.len());
-        let padding = self.screen_cols - lhs_status.len();
-        try!(write!(out, "{0}{1}{2: >3$}", clear::AfterCursor, lhs_status, rhs_status, padding));
+        try!(self.render_completion(out, cx, cy));
+        // Render status bar
+        try!(write!(out, "{}{}", clear::AfterCursor, style::Invert));
+        let lhs_status = status::expand(&self.config.status_left, |field| self.status_field(field));
+        let rhs_status = status::expand(&self.config.status_right, |field| self.status_field(field));
+        let status_bar = status::layout(&lhs_status, &rhs_status, self.screen_cols);
+        try!(write!(out, "{}{}", clear::AfterCursor, status_bar));
         try!(write!(out, "{}\r\n", style::Reset));
         match self.status_message {
             Some(ref msg) => {
+                // A message wider than the screen would scroll the terminal
+                let msg: String = msg.chars().take(self.screen_cols).collect();
                 try!(write!(out, "{}\r\n", msg));
             },
             None => {}
         };
 
-        // Put the cursor in the right spot.
-        let mut cx = 1;
-        let file_row = self.row_offset + self.cursor_y;
-        if file_row < self.rows.len() {
-            for j in self.col_offset..(self.cursor_x + self.col_offset) {
-                if let Some(ch) = self.rows[file_row].content.chars().nth(j) {
-                    if ch == TAB {
-                        cx += 3-((cx)%4);
-                    }
-                    cx += 1;
-                }
+        try!(write!(out, "{}{}", cursor::Goto(cx as u16, cy as u16), cursor::Show));
+        Ok(())
+    }
+
+    /// Draws the completion popup over the rows, below the cursor at screen
+    /// position `(cx, cy)` or above it if there is more room there. The rows
+    /// are redrawn in full each time, so nothing is left behind once the
+    /// popup closes.
+    fn render_completion<W: Write>(&self, out: &mut W, cx: usize, cy: usize) -> io::Result<()> {
+        let completion = match self.completion {
+            Some(ref completion) => completion,
+            None => return Ok(()),
+        };
+        let wanted = cmp::min(completion.candidates.len(), COMPLETION_ROWS);
+        let below = self.screen_rows.saturating_sub(cy);
+        let above = cy.saturating_sub(1);
+        let (height, top) = if below >= wanted || below >= above {
+            (cmp::min(wanted, below), cy + 1)
+        } else {
+            let height = cmp::min(wanted, above);
+            (height, cy - height)
+        };
+        if height == 0 {
+            return Ok(());
+        }
+        let longest = completion.candidates.iter().map(|word| word.chars().count()).max().unwrap_or(0);
+        let width = cmp::min(longest + 2, self.screen_cols);
+        // Line the completions up with the start of the word being typed
+        let left = cmp::max(cx.saturating_sub(completion.prefix.chars().count()), 1);
+        let left = cmp::min(left, self.screen_cols + 1 - width);
+        let first = (completion.selected + 1).saturating_sub(height);
+        for (i, word) in completion.candidates.iter().enumerate().skip(first).take(height) {
+            let text: String = format!(" {}", word).chars().take(width).collect();
+            if i == completion.selected {
+                try!(write!(out, "{}{}", color::Bg(color::Blue), color::Fg(color::LightWhite)));
+            } else {
+                try!(write!(out, "{}{}", color::Bg(color::LightBlack), color::Fg(color::White)));
             }
+            try!(write!(out, "{}{:<3$}{}", cursor::Goto(left as u16, (top + i - first) as u16),
+                        text, color::Bg(color::Reset), width));
         }
-        try!(write!(out, "{}{}", cursor::Goto(cx as u16, (self.cursor_y+1) as u16), cursor::Show));
+        // The status bar is drawn from where the rows left off
+        try!(write!(out, "{}{}", color::Fg(color::Reset), cursor::Goto(1, self.screen_rows as u16 + 1)));
         Ok(())
     }
 
+    /// Looks up the value of a field of the status bar template.
+    fn status_field(&self, field: &str) -> Option<String> {
+        let (file_row, file_col) = self.cursor_position();
+        let value = match field {
+            "filename" => self.filename.clone().unwrap_or("(no file)".to_owned()),
+            "modified" => if self.modified { " (modified)".to_owned() } else { String::new() },
+            "filetype" => {
+                self.syntax_highlight.as_ref().map_or("text".to_owned(), |rule| rule.name.clone())
+            },
+            "encoding" => "utf-8".to_owned(),
+            "line_ending" => self.line_ending.name().to_owned(),
+            "line" => format!("{}", file_row + 1),
+            "column" => format!("{}", file_col + 1),
+            "lines" => format!("{}", self.rows.len()),
+            "percent" => {
+                let percent = if self.rows.len() == 0 {
+                    100
+                } else {
+                    cmp::min(100 * (file_row + 1) / self.rows.len(), 100)
+                };
+                format!("{}%", percent)
+            },
+            "selection" => match (self.block(), self.selection_size()) {
+                (Some((first, last, left, right)), _) => {
+                    format!("{}x{} block", last - first + 1, right - left)
+                },
+                (None, Some((1, chars))) => format!("{} selected", chars),
+                (None, Some((lines, chars))) => format!("{} lines, {} selected", lines, chars),
+                (None, None) => String::new(),
+            },
+            "branch" => self.git_branch.clone().unwrap_or(String::new()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
     pub fn open_file(&mut self, filename: &str) -> io::Result<()> {
         debug!("open_file {}", filename);
         self.modified = false;
         self.filename = Some(filename.to_owned());
+        self.syntax_highlight = SyntaxHighlightRule::for_filename(filename);
+        self.git_branch = status::git_branch(filename);
+        self.selection_anchor = None;
+        self.block_anchor = None;
+        self.extra_cursors.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.last_edit = None;
         let file = try!(OpenOptions::new()
                         .read(true)
                         .write(true)
                         .create(true)
                         .open(filename));
-        let input = BufReader::new(&file);
+        let mut input = BufReader::new(&file);
         self.rows.clear();
-        for (at, line) in input.lines().enumerate() {
-            let l = try!(line);
-            self.rows.push(Row::new(at, &l));
+        self.line_ending = LineEnding::Lf;
+        let mut line = String::new();
+        let mut at = 0;
+        while try!(input.read_line(&mut line)) > 0 {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                    // The first line decides the line ending for the file
+                    if at == 0 {
+                        self.line_ending = LineEnding::Crlf;
+                    }
+                }
+            }
+            self.rows.push(Row::new(at, &line));
+            line.clear();
+            at += 1;
         }
         Ok(())
     }
@@ -289,21 +864,225 @@ impl Editor {
                             .create(true)
                             .open(filename));
         for row in &self.rows {
-            try!(writeln!(file, "{}", row.content));
+            try!(write!(file, "{}{}", row.content, self.line_ending.as_str()));
         }
         self.modified = false;
         Ok(())
     }
 
+    /// Returns the cursor's position in the file as a (row, column) pair.
+    #[inline(always)]
+    pub fn cursor_position(&self) -> (usize, usize) {
+        (self.row_offset + self.cursor_y, self.col_offset + self.cursor_x)
+    }
+
+    /// Moves the cursor to the given position in the file, scrolling the view
+    /// so it stays on screen.
+    pub fn set_cursor_position(&mut self, row: usize, col: usize) {
+        self.scroll_to(row, col);
+    }
+
+    /// Scrolls the view so the cursor is on screen.
+    fn scroll(&mut self) {
+        let (row, col) = self.cursor_position();
+        self.scroll_to(row, col);
+    }
+
+    fn scroll_to(&mut self, row: usize, col: usize) {
+        let line = self.line_of(row, col);
+        let top_segments = self.segments_in_row(self.row_offset);
+        let top = (self.row_offset, cmp::min(self.wrap_offset, top_segments - 1));
+        // Keep a margin of lines between the cursor and the edges of the
+        // screen, except where the file starts or ends
+        let margin = self.scroll_margin();
+        let upper = self.walk_lines(line, margin, CursorDirection::Up);
+        let lower = self.walk_lines(line, margin, CursorDirection::Down);
+        let top = if upper < top {
+            upper
+        } else {
+            // The top of the screen needed to show the lines below the cursor
+            let needed = self.walk_lines(lower, self.screen_rows.saturating_sub(1), CursorDirection::Up);
+            cmp::max(top, needed)
+        };
+        self.row_offset = top.0;
+        self.wrap_offset = top.1;
+
+        if self.config.soft_wrap {
+            self.col_offset = 0;
+        } else if col < self.col_offset {
+            self.col_offset = col;
+        } else if let Some(r) = self.rows.get(row) {
+            // Tabs take up several columns, so compare rendered widths
+            let cols = r.render_cols(self.tab_width());
+            while self.col_offset < col && cols[col] - cols[self.col_offset] >= self.screen_cols {
+                self.col_offset += 1;
+            }
+        }
+        self.cursor_y = row - self.row_offset;
+        self.cursor_x = col - self.col_offset;
+    }
+
+    /// Returns how many lines to keep between the cursor and the top or
+    /// bottom of the screen.
+    fn scroll_margin(&self) -> usize {
+        cmp::min(self.config.scroll_margin, self.screen_rows.saturating_sub(1) / 2)
+    }
+
+    /// Returns the screen line showing `(row, col)`, as the row and the index
+    /// of its wrapped segment.
+    fn line_of(&self, row: usize, col: usize) -> (usize, usize) {
+        match self.rows.get(row) {
+            Some(r) if self.config.soft_wrap => (row, wrap_segment(&self.wrap_points(r), col)),
+            _ => (row, 0),
+        }
+    }
+
+    /// Returns the screen line `n` lines up or down from `line`, stopping at
+    /// the start or end of the file.
+    fn walk_lines(&self, line: (usize, usize), n: usize, dir: CursorDirection) -> (usize, usize) {
+        let (mut row, mut segment) = line;
+        for _ in 0..n {
+            match dir {
+                CursorDirection::Up => {
+                    if segment > 0 {
+                        segment -= 1;
+                    } else if row > 0 {
+                        row -= 1;
+                        segment = self.segments_in_row(row) - 1;
+                    } else {
+                        break;
+                    }
+                },
+                CursorDirection::Down => {
+                    if segment + 1 < self.segments_in_row(row) {
+                        segment += 1;
+                    } else if row < self.rows.len() {
+                        row += 1;
+                        segment = 0;
+                    } else {
+                        break;
+                    }
+                },
+                _ => unreachable!(),
+            }
+        }
+        (row, segment)
+    }
+
+    /// Moves the cursor to the given position, clamped to the file, and
+    /// scrolls so that it is in the middle of the screen.
+    pub fn jump_to(&mut self, row: usize, col: usize) {
+        let row = cmp::min(row, self.rows.len().saturating_sub(1));
+        let col = self.clamp_col(row, col);
+        self.center_on(row, col);
+    }
+
+    /// Scrolls so the cursor is on the middle line of the screen.
+    pub fn center_view(&mut self) {
+        let (row, col) = self.cursor_position();
+        self.center_on(row, col);
+    }
+
+    /// Moves the cursor to the given position, scrolling so that it is on the
+    /// middle line of the screen.
+    fn center_on(&mut self, row: usize, col: usize) {
+        let line = self.line_of(row, col);
+        let top = self.walk_lines(line, self.screen_rows / 2, CursorDirection::Up);
+        self.row_offset = top.0;
+        self.wrap_offset = top.1;
+        self.col_offset = 0;
+        self.scroll_to(row, col);
+    }
+
+    /// Scrolls the view up or down by one line, leaving the cursor on the
+    /// same row unless that would go past the scroll margin.
+    pub fn scroll_view(&mut self, dir: CursorDirection) {
+        let top = (self.row_offset, self.wrap_offset);
+        let new_top = self.walk_lines(top, 1, dir);
+        if new_top == top || new_top.0 >= self.rows.len() {
+            return;
+        }
+        let (row, col) = self.cursor_position();
+        let line = self.line_of(row, col);
+        let margin = self.scroll_margin();
+        let first = self.walk_lines(new_top, margin, CursorDirection::Down);
+        let last = self.walk_lines(new_top, self.screen_rows.saturating_sub(margin + 1), CursorDirection::Down);
+        let target = if line < first {
+            first
+        } else if line > last {
+            last
+        } else {
+            line
+        };
+
+        self.row_offset = new_top.0;
+        self.wrap_offset = new_top.1;
+        let col = if target == line {
+            col
+        } else if self.config.soft_wrap {
+            let x = self.x_in_segment(row, col);
+            self.col_in_segment(target.0, target.1, x)
+        } else {
+            self.clamp_col(target.0, col)
+        };
+        self.scroll_to(target.0, col);
+    }
+
+    /// Moves the cursor to the start of the file.
+    pub fn cursor_to_start_of_buffer(&mut self) {
+        self.set_cursor_position(0, 0);
+    }
+
+    /// Moves the cursor to the end of the file.
+    pub fn cursor_to_end_of_buffer(&mut self) {
+        let (row, col) = self.end_position();
+        self.set_cursor_position(row, col);
+    }
+
+    /// Returns the number of lines in the file.
+    pub fn line_count(&self) -> usize {
+        self.rows.len()
+    }
 
     #[inline(always)]
-    fn left_edge(&self) -> bool {
-        self.cursor_x == 0
+    fn tab_width(&self) -> usize {
+        cmp::max(self.config.tab_width, 1)
     }
 
     #[inline(always)]
-    fn right_edge(&self) -> bool {
-        self.cursor_x == self.screen_cols - 1
+    fn wrap_indicator_width(&self) -> usize {
+        if self.config.wrap_indicator {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn wrap_points(&self, row: &Row) -> Vec<usize> {
+        row.wrap_points(self.screen_cols, self.wrap_indicator_width(),
+                        self.config.wrap_at_words, self.tab_width())
+    }
+
+    /// Returns the number of screen lines row `row_idx` takes up.
+    fn segments_in_row(&self, row_idx: usize) -> usize {
+        match self.rows.get(row_idx) {
+            Some(row) if self.config.soft_wrap => self.wrap_points(row).len(),
+            _ => 1,
+        }
+    }
+
+    /// Clamps `pos` to a position that exists in the file.
+    fn clamp_position(&self, pos: (usize, usize)) -> (usize, usize) {
+        let row = cmp::min(pos.0, self.rows.len());
+        (row, self.clamp_col(row, pos.1))
+    }
+
+    /// Clamps `col` to the length of row `row_idx`.
+    fn clamp_col(&self, row_idx: usize, col: usize) -> usize {
+        match self.rows.get(row_idx) {
+            Some(row) => cmp::min(col, row.len()),
+            None => 0,
+        }
     }
 
     #[inline(always)]
@@ -316,272 +1095,2199 @@ impl Editor {
         self.cursor_y == self.screen_rows - 1
     }
 
+    /// Moves every cursor one step in direction `dir`.
     pub fn move_cursor(&mut self, dir: CursorDirection) {
+        self.at_each_cursor(|editor| editor.step_cursor(dir));
+    }
+
+    fn step_cursor(&mut self, dir: CursorDirection) {
         use self::CursorDirection::*;
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
-        let row_exists = file_row < self.rows.len();
+        let (file_row, file_col) = self.cursor_position();
 
-        match dir {
+        if self.config.soft_wrap && (dir == Up || dir == Down) {
+            return self.move_cursor_visually(dir);
+        }
+
+        let (row, col) = match dir {
             Left => {
-                if self.left_edge() {
-                    // We're on the left edge of the terminal window
-                    if self.col_offset > 0 {
-                        // Shift the frame over
-                        self.col_offset -= 1;
-                    } else {
-                        // Move to the end of the previous line
-                        if file_row > 0 {
-                            self.cursor_y -= 1;
-                            self.cursor_x = self.rows[file_row-1].content.len();
-                            if self.cursor_x > self.screen_cols - 1 {
-                                self.col_offset = self.cursor_x - self.screen_cols + 1;
-                                self.cursor_x = self.screen_cols - 1;
-                            }
-                        }
-                    }
+                if file_col > 0 {
+                    (file_row, file_col - 1)
+                } else if file_row > 0 {
+                    // Move to the end of the previous line
+                    (file_row - 1, self.rows[file_row-1].len())
                 } else {
-                    // We aren't on the left edge, just move the cursor one to the left
-                    self.cursor_x -= 1;
+                    return;
                 }
             },
             Right => {
-                if row_exists && self.rows[file_row].column_exists(file_col) {
-                    if self.right_edge() {
-                        self.col_offset += 1;
-                    } else {
-                        self.cursor_x += 1;
-                    }
-                } else if row_exists && self.rows[file_row].is_end(file_col) {
-                    self.cursor_x = 0;
-                    self.col_offset = 0;
-                    if self.bottom_edge() {
-                        self.row_offset += 1;
-                    } else {
-                        self.cursor_y += 1;
-                    }
+                match self.rows.get(file_row) {
+                    Some(row) if row.column_exists(file_col) => (file_row, file_col + 1),
+                    Some(row) if row.is_end(file_col) => (file_row + 1, 0),
+                    _ => return,
                 }
             },
             Up => {
-                if self.top_edge() {
-                    if self.row_offset > 0 {
-                        self.row_offset -= 1;
-                    }
+                if file_row > 0 {
+                    (file_row - 1, file_col)
                 } else {
-                    self.cursor_y -= 1;
+                    return;
                 }
             },
             Down => {
                 if file_row < self.rows.len() {
-                    if self.bottom_edge() {
-                        self.row_offset += 1;
-                    } else {
-                        self.cursor_y += 1;
-                    }
+                    (file_row + 1, file_col)
+                } else {
+                    return;
                 }
             }
-        }
+        };
+        let col = self.clamp_col(row, col);
+        self.set_cursor_position(row, col);
+    }
+
+    /// Moves the cursor up or down by one screen line of a soft-wrapped row,
+    /// keeping it in the same screen column where possible.
+    fn move_cursor_visually(&mut self, dir: CursorDirection) {
+        let (file_row, file_col) = self.cursor_position();
+        let (_, segment) = self.line_of(file_row, file_col);
+        let x = self.x_in_segment(file_row, file_col);
 
-        // recalculate position
-        let new_file_row = self.row_offset + self.cursor_y;
-        let new_file_col = self.col_offset + self.cursor_x;
-        if let Some(row) = self.rows.get(new_file_row) {
-            if new_file_col > row.content.len() {
-                let cx = (self.cursor_x as isize) - ((new_file_col as isize) - (row.content.len() as isize));
-                if cx < 0 {
-                    self.col_offset = ((self.col_offset as isize) + cx) as usize;
-                    self.cursor_x = 0;
+        let (row, segment) = match dir {
+            CursorDirection::Up => {
+                if segment > 0 {
+                    (file_row, segment - 1)
+                } else if file_row > 0 {
+                    (file_row - 1, self.segments_in_row(file_row - 1) - 1)
                 } else {
-                    self.cursor_x = cx as usize;
+                    return;
                 }
-            }
-        }
+            },
+            CursorDirection::Down => {
+                if segment + 1 < self.segments_in_row(file_row) {
+                    (file_row, segment + 1)
+                } else if file_row < self.rows.len() {
+                    (file_row + 1, 0)
+                } else {
+                    return;
+                }
+            },
+            _ => unreachable!(),
+        };
+        let col = self.col_in_segment(row, segment, x);
+        self.set_cursor_position(row, col);
     }
 
-    pub fn cursor_to_start_of_line(&mut self) {
-        self.col_offset = 0;
-        self.cursor_x = 0;
+    /// Returns how many screen columns into its wrapped screen line column
+    /// `col` of row `row_idx` is displayed.
+    fn x_in_segment(&self, row_idx: usize, col: usize) -> usize {
+        match self.rows.get(row_idx) {
+            Some(row) => {
+                let points = self.wrap_points(row);
+                let cols = row.render_cols(self.tab_width());
+                cols[col] - cols[points[wrap_segment(&points, col)]]
+            },
+            None => 0,
+        }
     }
 
-    pub fn cursor_to_end_of_line(&mut self) {
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
-        let row_exists = file_row < self.rows.len();
-
-        if row_exists {
-            for _ in file_col..self.rows[file_row].content.len() {
-                self.move_cursor(CursorDirection::Right)
-            }
+    /// Returns the column of row `row_idx` displayed `x` screen columns into
+    /// its `segment`th wrapped screen line.
+    fn col_in_segment(&self, row_idx: usize, segment: usize, x: usize) -> usize {
+        let row = match self.rows.get(row_idx) {
+            Some(row) => row,
+            None => return 0,
+        };
+        let points = self.wrap_points(row);
+        let start = points[segment];
+        let end = match points.get(segment + 1) {
+            // The start of the next screen line belongs to that line
+            Some(&next) => next - 1,
+            None => row.len(),
+        };
+        let cols = row.render_cols(self.tab_width());
+        let mut col = start;
+        while col < end && cols[col + 1] - cols[start] <= x {
+            col += 1;
         }
+        col
     }
 
-    fn find_previous_non_alphanumeric_char_index(&self) -> Option<usize> {
-        // Search backwards for the previous non-alphanumeric character
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
-
-        if file_row < self.rows.len() {
-            Some({
-                let row = &self.rows[file_row];
-                let len = row.content.len();
-                // Find the index of the previous non-alphanumeric character
-                row.content.chars()
-                    .rev()
-                    .skip(len - file_col)
-                    .take_while(|c| c.is_alphanumeric())
-                    .count()
-            })
-        } else {
-            None
-        }
+    fn char_at(&self, row: usize, col: usize) -> Option<char> {
+        self.rows.get(row).and_then(|r| r.content.chars().nth(col))
     }
 
-    pub fn cursor_to_left_word(&mut self) {
-        // Search backwards for the previous non-alphanumeric character
-        if let Some(num) = self.find_previous_non_alphanumeric_char_index() {
-            for _ in 0..(num+1) {
-                self.move_cursor(CursorDirection::Left);
+    /// Returns the position of the bracket matching the one at `(row, col)`.
+    pub fn find_matching_bracket(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let ch = match self.char_at(row, col) {
+            Some(ch) => ch,
+            None => return None,
+        };
+        for &(open, close) in BRACKET_PAIRS.iter() {
+            if ch == open {
+                return self.scan_for_bracket(row, col, open, close, true);
+            } else if ch == close {
+                return self.scan_for_bracket(row, col, close, open, false);
             }
         }
+        None
     }
 
-    pub fn cursor_to_right_word(&mut self) {
-        // Search forwards for the next non-alphanumeric character
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
-
-        if file_row < self.rows.len() {
-            let num = {
-                let row = &self.rows[file_row];
-                // Find the next index
-                row.content.chars()
-                    .skip(file_col+1)
-                    .take_while(|c| c.is_alphanumeric())
-                    .count()+1
-            };
-            for _ in 0..num {
-                self.move_cursor(CursorDirection::Right);
+    /// Looks for the `other` bracket closing the `this` bracket at `(row, col)`,
+    /// skipping over nested pairs.
+    fn scan_for_bracket(&self, row: usize, col: usize, this: char, other: char,
+                        forwards: bool) -> Option<(usize, usize)> {
+        let mut depth = 0;
+        let mut check = |ch: char| {
+            if ch == this {
+                depth += 1;
+            } else if ch == other {
+                depth -= 1;
+                return depth == 0;
             }
+            false
+        };
+        if forwards {
+            let last_row = cmp::min(row + BRACKET_SEARCH_ROWS, self.rows.len());
+            for r in row..last_row {
+                let skip = if r == row { col } else { 0 };
+                for (c, ch) in self.rows[r].content.chars().enumerate().skip(skip) {
+                    if check(ch) {
+                        return Some((r, c));
+                    }
+                }
+            }
+        } else {
+            let first_row = (row + 1).saturating_sub(BRACKET_SEARCH_ROWS);
+            for r in (first_row..row+1).rev() {
+                let chars: Vec<char> = self.rows[r].content.chars().collect();
+                let end = if r == row { col + 1 } else { chars.len() };
+                for c in (0..end).rev() {
+                    if check(chars[c]) {
+                        return Some((r, c));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the position of the bracket under or just before the cursor,
+    /// and of the bracket matching it.
+    fn bracket_pair_at_cursor(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (file_row, file_col) = self.cursor_position();
+        let mut candidates = vec![file_col];
+        if file_col > 0 {
+            candidates.push(file_col - 1);
+        }
+        for col in candidates {
+            if let Some(other) = self.find_matching_bracket(file_row, col) {
+                return Some(((file_row, col), other));
+            }
+        }
+        None
+    }
+
+    /// Returns the rendered positions of the brackets to highlight.
+    fn bracket_highlights(&self) -> Vec<(usize, usize)> {
+        if !self.config.highlight_matching_bracket {
+            return vec![];
+        }
+        match self.bracket_pair_at_cursor() {
+            Some((this, other)) => {
+                vec![this, other].into_iter()
+                    .map(|(r, c)| (r, self.rows[r].render_col(c, self.tab_width())))
+                    .collect()
+            },
+            None => vec![],
+        }
+    }
+
+    /// Moves the cursor to the bracket matching the one under or before it,
+    /// returning whether there was one.
+    pub fn jump_to_matching_bracket(&mut self) -> bool {
+        match self.bracket_pair_at_cursor() {
+            Some((_, (row, col))) => {
+                self.set_cursor_position(row, col);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn cursor_to_start_of_line(&mut self) {
+        self.at_each_cursor(|editor| {
+            let (file_row, _) = editor.cursor_position();
+            editor.set_cursor_position(file_row, 0);
+        });
+    }
+
+    pub fn cursor_to_end_of_line(&mut self) {
+        self.at_each_cursor(|editor| {
+            let (file_row, _) = editor.cursor_position();
+            if let Some(len) = editor.rows.get(file_row).map(|row| row.len()) {
+                editor.set_cursor_position(file_row, len);
+            }
+        });
+    }
+
+    /// Returns the start of the word, or subword, before `pos`, looking back
+    /// across rows.
+    fn word_start_before(&self, pos: (usize, usize), subwords: bool) -> (usize, usize) {
+        let (mut row, mut col) = self.clamp_position(pos);
+        loop {
+            if let Some(r) = self.rows.get(row) {
+                let chars: Vec<char> = r.content.chars().collect();
+                let spans = words::word_spans(&chars, subwords);
+                if let Some(&(start, _)) = spans.iter().rev().find(|&&(start, _)| start < col) {
+                    return (row, start);
+                }
+            }
+            if row == 0 {
+                return (0, 0);
+            }
+            row -= 1;
+            col = self.rows[row].len() + 1;
+        }
+    }
+
+    /// Returns the end of the word, or subword, after `pos`, looking ahead
+    /// across rows.
+    fn word_end_after(&self, pos: (usize, usize), subwords: bool) -> (usize, usize) {
+        let (mut row, mut col) = self.clamp_position(pos);
+        while row < self.rows.len() {
+            let chars: Vec<char> = self.rows[row].content.chars().collect();
+            let spans = words::word_spans(&chars, subwords);
+            if let Some(&(_, end)) = spans.iter().find(|&&(_, end)| end > col) {
+                return (row, end);
+            }
+            row += 1;
+            col = 0;
+        }
+        self.end_position()
+    }
+
+    /// Moves the cursor to the start of the word before it.
+    pub fn cursor_to_left_word(&mut self) {
+        self.at_each_cursor(|editor| {
+            let (row, col) = editor.word_start_before(editor.cursor_position(), false);
+            editor.set_cursor_position(row, col);
+        });
+    }
+
+    /// Moves the cursor to the end of the word after it.
+    pub fn cursor_to_right_word(&mut self) {
+        self.at_each_cursor(|editor| {
+            let (row, col) = editor.word_end_after(editor.cursor_position(), false);
+            editor.set_cursor_position(row, col);
+        });
+    }
+
+    /// Moves the cursor to the start of the subword before it, stopping at
+    /// underscores and the humps of camelCase names.
+    pub fn cursor_to_left_subword(&mut self) {
+        self.at_each_cursor(|editor| {
+            let (row, col) = editor.word_start_before(editor.cursor_position(), true);
+            editor.set_cursor_position(row, col);
+        });
+    }
+
+    /// Moves the cursor to the end of the subword after it.
+    pub fn cursor_to_right_subword(&mut self) {
+        self.at_each_cursor(|editor| {
+            let (row, col) = editor.word_end_after(editor.cursor_position(), true);
+            editor.set_cursor_position(row, col);
+        });
+    }
+
+    /// Moves the cursor to `pos`, first starting a selection at the cursor if
+    /// `extend` is set and there isn't one already.
+    fn move_cursor_to(&mut self, pos: (usize, usize), extend: bool) {
+        if extend && self.selection_anchor.is_none() {
+            self.selection_anchor = Some(self.cursor_position());
+        }
+        self.set_cursor_position(pos.0, pos.1);
+    }
+
+    fn is_blank_row(&self, row: usize) -> bool {
+        self.rows.get(row).map_or(true, |r| r.content.trim().is_empty())
+    }
+
+    /// Moves the cursor to the blank row before or after the paragraph it is
+    /// in, or the next one over if it is between paragraphs. Extends the
+    /// selection if `extend` is set.
+    pub fn cursor_to_paragraph(&mut self, dir: CursorDirection, extend: bool) {
+        let (mut row, _) = self.cursor_position();
+        let pos = if dir == CursorDirection::Up {
+            while row > 0 && self.is_blank_row(row) {
+                row -= 1;
+            }
+            while row > 0 && !self.is_blank_row(row) {
+                row -= 1;
+            }
+            (row, 0)
+        } else {
+            while row < self.rows.len() && self.is_blank_row(row) {
+                row += 1;
+            }
+            while row < self.rows.len() && !self.is_blank_row(row) {
+                row += 1;
+            }
+            if row < self.rows.len() { (row, 0) } else { self.end_position() }
+        };
+        self.move_cursor_to(pos, extend);
+    }
+
+    /// Returns the position of the opening bracket of the innermost block
+    /// around `(row, col)`.
+    fn enclosing_bracket(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let mut depth = 0;
+        let first_row = row.saturating_sub(BRACKET_SEARCH_ROWS);
+        for r in (first_row..cmp::min(row + 1, self.rows.len())).rev() {
+            let chars: Vec<char> = self.rows[r].content.chars().collect();
+            let end = if r == row { cmp::min(col, chars.len()) } else { chars.len() };
+            for c in (0..end).rev() {
+                if BRACKET_PAIRS.iter().any(|&(_, close)| close == chars[c]) {
+                    depth += 1;
+                } else if BRACKET_PAIRS.iter().any(|&(open, _)| open == chars[c]) {
+                    if depth == 0 {
+                        return Some((r, c));
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+        None
+    }
+
+    /// Moves the cursor to the opening bracket of the block around it, going
+    /// out a level each time it is used. Returns false if there is no such
+    /// block.
+    pub fn cursor_to_enclosing_block(&mut self, extend: bool) -> bool {
+        let (row, col) = self.cursor_position();
+        match self.enclosing_bracket(row, col) {
+            Some(pos) => {
+                self.move_cursor_to(pos, extend);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Moves the cursor to the opening bracket of the next block after it.
+    /// Returns false if there is no such block.
+    pub fn cursor_to_next_block(&mut self, extend: bool) -> bool {
+        let (row, col) = self.cursor_position();
+        let last_row = cmp::min(row + BRACKET_SEARCH_ROWS, self.rows.len());
+        for r in row..last_row {
+            let skip = if r == row { col + 1 } else { 0 };
+            let found = self.rows[r].content.chars().enumerate().skip(skip)
+                .find(|&(_, ch)| BRACKET_PAIRS.iter().any(|&(open, _)| open == ch));
+            if let Some((c, _)) = found {
+                self.move_cursor_to((r, c), extend);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Moves the cursor to the start of the previous or next function
+    /// definition, as found by the filetype's function pattern. Returns false
+    /// if there is none, or the filetype doesn't have a pattern.
+    pub fn cursor_to_function(&mut self, dir: CursorDirection, extend: bool) -> bool {
+        let pattern = match self.syntax_highlight {
+            Some(SyntaxHighlightRule { function_pattern: Some(ref pattern), .. }) => pattern,
+            _ => return false,
+        };
+        let (row, _) = self.cursor_position();
+        let found = if dir == CursorDirection::Up {
+            (0..cmp::min(row, self.rows.len())).rev()
+                .find(|&r| pattern.is_match(&self.rows[r].content))
+        } else {
+            (row + 1..self.rows.len()).find(|&r| pattern.is_match(&self.rows[r].content))
+        };
+        match found {
+            Some(r) => {
+                let indent = leading_whitespace(&self.rows[r].content).chars().count();
+                self.move_cursor_to((r, indent), extend);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn page_cursor(&mut self, dir: CursorDirection) {
+        if !self.config.soft_wrap {
+            // Start from the top or bottom of the screen, so a whole screen's
+            // worth of rows goes by
+            let (file_row, file_col) = self.cursor_position();
+            let row = if dir == CursorDirection::Up && !self.top_edge() {
+                self.row_offset
+            } else if dir == CursorDirection::Down && !self.bottom_edge() {
+                cmp::min(self.row_offset + self.screen_rows - 1, self.rows.len())
+            } else {
+                file_row
+            };
+            let col = self.clamp_col(row, file_col);
+            self.set_cursor_position(row, col);
+        }
+        for _ in 0..self.screen_rows {
+            self.step_cursor(dir);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.begin_edit(EditKind::Delete);
+        // Deleting a block selection's text is all a backspace does with one
+        if !self.take_block_selection(false) {
+            self.at_each_cursor(Editor::backspace_at_cursor);
+        }
+        self.end_edit(EditKind::Delete);
+    }
+
+    fn backspace_at_cursor(&mut self) {
+        debug!("backspace");
+        let (file_row, file_col) = self.cursor_position();
+        if file_row >= self.rows.len() || (file_col == 0 && file_row == 0) {
+            return;
+        }
+        self.begin_edit(EditKind::Delete);
+        if file_col == 0 {
+            // Append to the prior row, then delete the current row
+            self.touch_rows(file_row - 1, file_row + 1);
+            let content = self.rows[file_row].content.clone();
+            let prior_row_len = self.rows[file_row-1].len();
+            self.rows[file_row-1].push_str(content);
+            self.rows.remove(file_row);
+            self.set_cursor_position(file_row - 1, prior_row_len);
+        } else {
+            // Just drop the char from the row, along with the other half of
+            // an empty pair
+            let pair = match (self.char_at(file_row, file_col - 1), self.char_at(file_row, file_col)) {
+                (Some(prev), Some(next)) => Some((prev, next)),
+                _ => None,
+            };
+            self.touch_rows(file_row, file_row + 1);
+            if self.config.auto_pair && pair.map_or(false, |pair| self.auto_pairs().contains(&pair)) {
+                self.rows[file_row].backspace(file_col + 1);
+            }
+            self.rows[file_row].backspace(file_col);
+            self.set_cursor_position(file_row, file_col - 1);
+        }
+
+        self.end_edit(EditKind::Delete);
+        debug!("number of rows: {}", self.rows.len());
+    }
+
+    /// Deletes back to the start of the word before each cursor, returning
+    /// the text deleted, with a line for each cursor.
+    pub fn backspace_word(&mut self) -> String {
+        let mut killed = vec![];
+        self.begin_edit(EditKind::Other);
+        self.at_each_cursor(|editor| {
+            let cursor = editor.clamp_position(editor.cursor_position());
+            // There is nothing to delete on the line past the end of the file
+            let end = if cursor.0 < editor.rows.len() { cursor } else { editor.end_position() };
+            let start = editor.word_start_before(cursor, false);
+            if start < end {
+                killed.push(editor.text_between(start, end));
+                editor.replace_range(start, end, "");
+            }
+        });
+        self.end_edit(EditKind::Other);
+        killed.reverse();
+        killed.join("\n")
+    }
+
+    /// Deletes back to the start of the line at each cursor, returning the
+    /// text deleted, with a line for each cursor.
+    pub fn backspace_to_start_of_line(&mut self) -> String {
+        let mut killed = vec![];
+        // Simply delete from here to the beginning of the line
+        self.begin_edit(EditKind::Other);
+        self.at_each_cursor(|editor| {
+            let cursor = editor.clamp_position(editor.cursor_position());
+            if cursor.0 < editor.rows.len() && cursor.1 > 0 {
+                killed.push(editor.text_between((cursor.0, 0), cursor));
+                editor.replace_range((cursor.0, 0), cursor, "");
+            }
+        });
+        self.end_edit(EditKind::Other);
+        killed.reverse();
+        killed.join("\n")
+    }
+
+    /// Removes whitespace from the end of every row, returning the number of
+    /// rows changed.
+    pub fn strip_trailing_whitespace(&mut self) -> usize {
+        if self.rows.iter().all(|row| row.trailing_whitespace_start() == row.len()) {
+            return 0;
+        }
+        self.begin_edit(EditKind::Other);
+        let mut stripped = 0;
+        for row_idx in 0..self.rows.len() {
+            let trailing = self.rows[row_idx].trailing_whitespace_start();
+            if trailing < self.rows[row_idx].len() {
+                self.touch_rows(row_idx, row_idx + 1);
+                self.rows[row_idx].truncate(trailing);
+                stripped += 1;
+            }
+        }
+        let (file_row, file_col) = self.cursor_position();
+        let col = self.clamp_col(file_row, file_col);
+        self.set_cursor_position(file_row, col);
+        self.end_edit(EditKind::Other);
+        stripped
+    }
+
+    /// Starts selecting from the cursor, or stops if a selection is already
+    /// being made. Returns whether a selection is now being made.
+    pub fn toggle_selection(&mut self) -> bool {
+        if self.selection_anchor.is_some() || self.block_anchor.is_some() {
+            self.selection_anchor = None;
+            self.block_anchor = None;
+        } else {
+            self.selection_anchor = Some(self.cursor_position());
+        }
+        self.selection_anchor.is_some()
+    }
+
+    /// Returns the start and end of the selection, if there is one.
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        self.selection_anchor.map(|anchor| {
+            let anchor = self.clamp_position(anchor);
+            let cursor = self.cursor_position();
+            if anchor <= cursor {
+                (anchor, cursor)
+            } else {
+                (cursor, anchor)
+            }
+        })
+    }
+
+    /// Returns the number of rows the selection touches and the number of
+    /// characters in it, counting line breaks.
+    fn selection_size(&self) -> Option<(usize, usize)> {
+        self.selection().map(|(start, end)| {
+            let chars = if start.0 == end.0 {
+                end.1 - start.1
+            } else {
+                let middle: usize = self.rows[start.0+1..end.0].iter().map(|r| r.len() + 1).sum();
+                (self.rows[start.0].len() - start.1 + 1) + middle + end.1
+            };
+            (end.0 - start.0 + 1, chars)
+        })
+    }
+
+    /// Returns the range of rendered columns of row `row_idx` that are
+    /// selected, including one past the end of the row for its line break.
+    fn selection_cols(&self, row_idx: usize) -> Option<(usize, usize)> {
+        if let Some((first, last, left, right)) = self.block() {
+            if row_idx < first || row_idx > last {
+                return None;
+            }
+            return Some((left, right));
+        }
+        let (start, end) = match self.selection() {
+            Some(range) => range,
+            None => return None,
+        };
+        if row_idx < start.0 || row_idx > end.0 || row_idx >= self.rows.len() {
+            return None;
+        }
+        let cols = self.rows[row_idx].render_cols(self.tab_width());
+        let first = if row_idx == start.0 { cols[start.1] } else { 0 };
+        let last = if row_idx == end.0 { cols[end.1] } else { cols[cols.len() - 1] + 1 };
+        Some((first, last))
+    }
+
+    /// Records that rows `first` up to `last` are about to be changed, so
+    /// their contents can be kept for undoing. Rows inserted at `first` are
+    /// covered by touching an empty range there, and rows pushed onto the end
+    /// by a range reaching past it.
+    fn touch_rows(&mut self, first: usize, last: usize) {
+        let len = self.rows.len();
+        let last = cmp::min(last, len);
+        let first = cmp::min(first, last);
+        let pending = match self.pending {
+            Some(ref mut pending) => pending,
+            None => return,
+        };
+        if !pending.dirty {
+            pending.dirty = true;
+            pending.start = first;
+            pending.old_rows = self.rows[first..last].iter().map(|row| row.content.clone()).collect();
+            pending.tail = len - last;
+            return;
+        }
+        // The rows around the ones already changed are still as they were
+        if first < pending.start {
+            let mut old_rows: Vec<String> = self.rows[first..pending.start].iter()
+                .map(|row| row.content.clone())
+                .collect();
+            old_rows.extend(pending.old_rows.drain(..));
+            pending.old_rows = old_rows;
+            pending.start = first;
+        }
+        let end = len - pending.tail;
+        if last > end {
+            pending.old_rows.extend(self.rows[end..last].iter().map(|row| row.content.clone()));
+            pending.tail = len - last;
+        }
+    }
+
+    /// Replaces `count` rows from `start` with `contents`.
+    fn splice_rows(&mut self, start: usize, count: usize, contents: &[String]) {
+        if count == contents.len() {
+            // Saves moving all the rows after them
+            for (row, content) in self.rows[start..start + count].iter_mut().zip(contents) {
+                *row = Row::with_content(content);
+            }
+            return;
+        }
+        let following = self.rows.split_off(start + count);
+        self.rows.truncate(start);
+        self.rows.extend(contents.iter().map(|content| Row::with_content(content)));
+        self.rows.extend(following);
+    }
+
+    /// Puts the cursor back where it was before or after an undone or redone
+    /// edit.
+    fn restore_cursor(&mut self, cursor: (usize, usize)) {
+        let cursor = self.clamp_position(cursor);
+        self.set_cursor_position(cursor.0, cursor.1);
+        self.extra_cursors.clear();
+        self.block_anchor = None;
+        self.last_edit = None;
+        self.modified = true;
+    }
+
+    /// Starts recording an edit, so it can be undone. Edits made before the
+    /// matching `end_edit` are undone along with it, as are further edits of
+    /// the same kind carrying on from where it left off.
+    fn begin_edit(&mut self, kind: EditKind) {
+        if self.edit_depth == 0 {
+            let cursor = self.cursor_position();
+            let continued = kind != EditKind::Other && self.last_edit == Some((kind, cursor));
+            let last = if continued { self.undo_stack.pop_back() } else { None };
+            self.pending = Some(match last {
+                // Carry on with the last edit, whose rows are as it left them
+                Some(change) => PendingChange {
+                    dirty: true,
+                    start: change.start,
+                    tail: self.rows.len() - (change.start + change.new_rows.len()),
+                    old_rows: change.old_rows,
+                    cursor_before: change.cursor_before,
+                },
+                None => PendingChange {
+                    dirty: false,
+                    start: 0,
+                    old_rows: vec![],
+                    tail: 0,
+                    cursor_before: cursor,
+                },
+            });
+        }
+        self.edit_depth += 1;
+    }
+
+    fn end_edit(&mut self, kind: EditKind) {
+        self.edit_depth -= 1;
+        if self.edit_depth > 0 {
+            return;
+        }
+        let pending = match self.pending.take() {
+            Some(ref pending) if !pending.dirty => return,
+            Some(pending) => pending,
+            None => return,
+        };
+        let end = self.rows.len() - pending.tail;
+        let new_rows: Vec<String> = self.rows[pending.start..end].iter()
+            .map(|row| row.content.clone())
+            .collect();
+        // Don't keep an edit that didn't change anything
+        if new_rows == pending.old_rows {
+            return;
+        }
+        self.undo_stack.push_back(Change {
+            start: pending.start,
+            old_rows: pending.old_rows,
+            new_rows: new_rows,
+            cursor_before: pending.cursor_before,
+            cursor_after: self.cursor_position(),
+        });
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.pop_front();
+        }
+        self.redo_stack.clear();
+        self.last_edit = Some((kind, self.cursor_position()));
+        self.modified = true;
+    }
+
+    /// Starts a group of edits to be undone all at once.
+    pub fn begin_undo_group(&mut self) {
+        self.begin_edit(EditKind::Other);
+    }
+
+    /// Ends a group of edits started with `begin_undo_group`.
+    pub fn end_undo_group(&mut self) {
+        self.end_edit(EditKind::Other);
+    }
+
+    /// Undoes the last edit, returning whether there was one.
+    pub fn undo(&mut self) -> bool {
+        match self.undo_stack.pop_back() {
+            Some(change) => {
+                self.splice_rows(change.start, change.new_rows.len(), &change.old_rows);
+                self.restore_cursor(change.cursor_before);
+                self.redo_stack.push(change);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Redoes the last undone edit, returning whether there was one.
+    pub fn redo(&mut self) -> bool {
+        match self.redo_stack.pop() {
+            Some(change) => {
+                self.splice_rows(change.start, change.old_rows.len(), &change.new_rows);
+                self.restore_cursor(change.cursor_after);
+                self.undo_stack.push_back(change);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns the contents of the buffer with the rows joined by newlines,
+    /// so that patterns can match across rows.
+    fn buffer_text(&self) -> String {
+        let lines: Vec<&str> = self.rows.iter().map(|row| &row.content[..]).collect();
+        lines.join("\n")
+    }
+
+    /// Returns the byte offset into `buffer_text` of a position in the buffer.
+    fn offset_of(&self, pos: (usize, usize)) -> usize {
+        let mut offset = 0;
+        for row in self.rows.iter().take(pos.0) {
+            offset += row.content.len() + 1;
+        }
+        match self.rows.get(pos.0) {
+            Some(row) => offset + row.byte_index(pos.1),
+            None => offset.saturating_sub(1),
+        }
+    }
+
+    /// Returns the position in the buffer of a byte offset into `buffer_text`.
+    fn position_of(&self, offset: usize) -> (usize, usize) {
+        let mut row_start = 0;
+        for (row_idx, row) in self.rows.iter().enumerate() {
+            let row_end = row_start + row.content.len();
+            if offset <= row_end {
+                return (row_idx, row.content[..offset - row_start].chars().count());
+            }
+            row_start = row_end + 1;
+        }
+        (self.rows.len(), 0)
+    }
+
+    /// Returns the start and end of the first match of `search` at or after
+    /// `from`.
+    pub fn find_next(&self, search: &Search, from: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let text = self.buffer_text();
+        search.find_at(&text, self.offset_of(from))
+            .map(|(start, end)| (self.position_of(start), self.position_of(end)))
+    }
+
+    /// Returns the start and end of the last match of `search` starting
+    /// before `before`.
+    pub fn find_prev(&self, search: &Search, before: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let text = self.buffer_text();
+        let limit = self.offset_of(before);
+        let mut found = None;
+        let mut offset = 0;
+        while offset <= text.len() {
+            match search.find_at(&text, offset) {
+                Some((start, end)) if start < limit => {
+                    found = Some((start, end));
+                    offset = next_char_offset(&text, start);
+                },
+                _ => break,
+            }
+        }
+        found.map(|(start, end)| (self.position_of(start), self.position_of(end)))
+    }
+
+    /// Returns the position after the last character of the buffer.
+    pub fn end_position(&self) -> (usize, usize) {
+        match self.rows.last() {
+            Some(row) => (self.rows.len() - 1, row.len()),
+            None => (0, 0),
+        }
+    }
+
+    /// Returns the position one character after `pos`, if it isn't the end
+    /// of the buffer.
+    pub fn position_after(&self, pos: (usize, usize)) -> Option<(usize, usize)> {
+        match self.rows.get(pos.0) {
+            Some(row) if pos.1 < row.len() => Some((pos.0, pos.1 + 1)),
+            Some(_) if pos.0 + 1 < self.rows.len() => Some((pos.0 + 1, 0)),
+            _ => None,
+        }
+    }
+
+    /// Replaces the text between `start` and `end` with `text`, leaving the
+    /// cursor after the new text and returning its position.
+    pub fn replace_range(&mut self, start: (usize, usize), end: (usize, usize), text: &str) -> (usize, usize) {
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(start.0, end.0 + 1);
+        while self.rows.len() <= end.0 {
+            self.rows.push(Row::empty());
+        }
+        let head = {
+            let row = &self.rows[start.0];
+            row.content[..row.byte_index(start.1)].to_owned()
+        };
+        let tail = {
+            let row = &self.rows[end.0];
+            row.content[row.byte_index(end.1)..].to_owned()
+        };
+
+        let mut lines: Vec<String> = text.split('\n').map(|l| l.to_owned()).collect();
+        lines[0].insert_str(0, &head);
+        let last = lines.len() - 1;
+        let new_end = (start.0 + last, lines[last].chars().count());
+        lines[last].push_str(&tail);
+
+        let new_rows: Vec<Row> = lines.iter().map(|l| Row::with_content(l)).collect();
+        let following = self.rows.split_off(end.0 + 1);
+        self.rows.truncate(start.0);
+        self.rows.extend(new_rows);
+        self.rows.extend(following);
+
+        self.set_cursor_position(new_end.0, new_end.1);
+        self.end_edit(EditKind::Other);
+        new_end
+    }
+
+    /// Replaces the match of `search` between `start` and `end` with
+    /// `template`, expanding any capture group references in it. Returns the
+    /// position after the new text.
+    pub fn replace_match(&mut self, search: &Search, start: (usize, usize), end: (usize, usize),
+                         template: &str) -> (usize, usize) {
+        let replacement = search.replacement(&self.buffer_text(), self.offset_of(start), template);
+        self.replace_range(start, end, &replacement)
+    }
+
+    /// Replaces every match of `search` from `from` onwards with `template`
+    /// as a single edit, returning how many were replaced. The cursor is left
+    /// after the last replacement.
+    pub fn replace_all(&mut self, search: &Search, template: &str, from: (usize, usize)) -> usize {
+        let text = self.buffer_text();
+        let mut matches = Vec::new();
+        let mut offset = self.offset_of(from);
+        let mut last_end = None;
+        while offset <= text.len() {
+            let (start, end) = match search.find_at(&text, offset) {
+                Some(m) => m,
+                None => break,
+            };
+            // An empty match right after the previous one isn't a new match
+            if start == end && last_end == Some(start) {
+                offset = next_char_offset(&text, start);
+                continue;
+            }
+            matches.push((start, end, search.replacement(&text, start, template)));
+            last_end = Some(end);
+            offset = if end > start { end } else { next_char_offset(&text, end) };
+        }
+        if matches.is_empty() {
+            return 0;
+        }
+
+        // Replace from the back so the earlier offsets stay valid
+        self.begin_edit(EditKind::Other);
+        let mut growth = 0isize;
+        for &(start, end, ref replacement) in matches.iter().rev() {
+            let (start_pos, end_pos) = (self.position_of(start), self.position_of(end));
+            self.replace_range(start_pos, end_pos, replacement);
+            growth += replacement.len() as isize - (end - start) as isize;
+        }
+        let final_end = matches[matches.len() - 1].1;
+        let (row, col) = self.position_of((final_end as isize + growth) as usize);
+        self.set_cursor_position(row, col);
+        self.end_edit(EditKind::Other);
+        matches.len()
+    }
+
+    /// Selects the text between `start` and `end`, leaving the cursor at `end`.
+    pub fn select_range(&mut self, start: (usize, usize), end: (usize, usize)) {
+        self.selection_anchor = Some(start);
+        self.set_cursor_position(end.0, end.1);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection_anchor = None;
+        self.block_anchor = None;
+    }
+
+    /// Returns the position shown at screen column `x` and line `y`, counting
+    /// from 1, or the nearest one to it in the file.
+    fn position_at_screen(&self, x: usize, y: usize) -> (usize, usize) {
+        let top_segments = self.segments_in_row(self.row_offset);
+        let top = (self.row_offset, cmp::min(self.wrap_offset, top_segments - 1));
+        let y = cmp::max(cmp::min(y, self.screen_rows), 1);
+        let (row_idx, segment) = self.walk_lines(top, y - 1, CursorDirection::Down);
+        let row = match self.rows.get(row_idx) {
+            Some(row) => row,
+            None => return self.clamp_position((row_idx, 0)),
+        };
+        let tab_width = self.tab_width();
+        let screen_x = x.saturating_sub(1);
+        if !self.config.soft_wrap {
+            let col = row.col_at(row.render_col(self.col_offset, tab_width) + screen_x, tab_width);
+            return (row_idx, col);
+        }
+        let points = self.wrap_points(row);
+        // Continuation lines start with the wrap indicator
+        let indent = if segment > 0 { self.wrap_indicator_width() } else { 0 };
+        let x = row.render_col(points[segment], tab_width) + screen_x.saturating_sub(indent);
+        let col = row.col_at(x, tab_width);
+        // Past the end of a wrapped line is its end, not the next line's start
+        match points.get(segment + 1) {
+            Some(&next) => (row_idx, cmp::min(col, next - 1)),
+            None => (row_idx, col),
+        }
+    }
+
+    /// Moves the cursor to where the mouse was clicked, at screen column `x`
+    /// and line `y`, dropping the selection and any other cursors.
+    pub fn click(&mut self, x: usize, y: usize) {
+        let (row, col) = self.position_at_screen(x, y);
+        self.clear_selection();
+        self.extra_cursors.clear();
+        self.set_cursor_position(row, col);
+    }
+
+    /// Selects from the cursor, or from where the selection was started, to
+    /// where the mouse has been dragged.
+    pub fn drag_to(&mut self, x: usize, y: usize) {
+        let pos = self.position_at_screen(x, y);
+        self.move_cursor_to(pos, true);
+    }
+
+    /// Selects the word double-clicked at screen column `x` and line `y`,
+    /// returning false if there is no word there.
+    pub fn select_word_at(&mut self, x: usize, y: usize) -> bool {
+        match self.word_at(self.position_at_screen(x, y)) {
+            Some((start, end)) => {
+                self.select_range(start, end);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns the rendered column position `pos` is displayed at.
+    fn render_col_of(&self, pos: (usize, usize)) -> usize {
+        self.rows.get(pos.0).map_or(0, |row| row.render_col(pos.1, self.tab_width()))
+    }
+
+    /// Returns the first and last rows of the block selection and the range
+    /// of rendered columns it covers, if there is one.
+    fn block(&self) -> Option<(usize, usize, usize, usize)> {
+        self.block_anchor.map(|(anchor_row, anchor_x)| {
+            let (row, col) = self.cursor_position();
+            // The cursor may have been moved off the block's edge by other means
+            let edge_col = self.rows.get(row).map_or(0, |r| r.col_at(self.block_x, self.tab_width()));
+            let x = if edge_col == col { self.block_x } else { self.render_col_of((row, col)) };
+            (cmp::min(anchor_row, row), cmp::max(anchor_row, row),
+             cmp::min(anchor_x, x), cmp::max(anchor_x, x))
+        })
+    }
+
+    /// Starts a block selection at the cursor if there isn't one, then moves
+    /// the block's corner at the cursor in direction `dir`. The corner can go
+    /// past the end of the cursor's row.
+    pub fn extend_block_selection(&mut self, dir: CursorDirection) {
+        let (row, col) = self.cursor_position();
+        if self.block_anchor.is_none() {
+            self.selection_anchor = None;
+            self.extra_cursors.clear();
+            self.block_x = self.render_col_of((row, col));
+            self.block_anchor = Some((row, self.block_x));
+        }
+        let row = match dir {
+            CursorDirection::Up => row.saturating_sub(1),
+            CursorDirection::Down => cmp::min(row + 1, self.rows.len().saturating_sub(1)),
+            CursorDirection::Left => {
+                self.block_x = self.block_x.saturating_sub(1);
+                row
+            },
+            CursorDirection::Right => {
+                self.block_x += 1;
+                row
+            },
+        };
+        let col = self.rows.get(row).map_or(0, |r| r.col_at(self.block_x, self.tab_width()));
+        self.set_cursor_position(row, col);
+    }
+
+    /// Returns the columns of row `row_idx` between rendered columns `left`
+    /// and `right`, first splitting any tabs across them into spaces and, if
+    /// `pad` is set, padding the row out to `left`.
+    fn block_cols(&mut self, row_idx: usize, left: usize, right: usize, pad: bool) -> (usize, usize) {
+        let tab_width = self.tab_width();
+        self.touch_rows(row_idx, row_idx + 1);
+        let row = &mut self.rows[row_idx];
+        row.split_tab_at(right, tab_width);
+        row.split_tab_at(left, tab_width);
+        if pad {
+            row.pad_to(left, tab_width);
+        }
+        let cols = row.render_cols(tab_width);
+        let start = (0..row.len()).find(|&i| cols[i] >= left).unwrap_or(row.len());
+        let end = (start..row.len()).find(|&i| cols[i] >= right).unwrap_or(row.len());
+        (start, end)
+    }
+
+    /// Returns the text in the block selection, a line for each row. Tabs
+    /// partly inside the block are copied as the spaces they cover.
+    fn block_text(&self) -> Option<String> {
+        let (first, last, left, right) = match self.block() {
+            Some(block) => block,
+            None => return None,
+        };
+        let lines: Vec<String> = (first..last + 1).map(|r| {
+            let mut line = String::new();
+            if let Some(row) = self.rows.get(r) {
+                let cols = row.render_cols(self.tab_width());
+                for (i, c) in row.content.chars().enumerate() {
+                    let (start, end) = (cols[i], cols[i + 1]);
+                    if start >= left && end <= right {
+                        line.push(c);
+                    } else if c == TAB && start < right && end > left {
+                        let covered = cmp::min(end, right) - cmp::max(start, left);
+                        line.extend(iter::repeat(' ').take(covered));
+                    }
+                }
+            }
+            line
+        }).collect();
+        Some(lines.join("\n"))
+    }
+
+    /// Deletes the text in the block selection and ends it, leaving a cursor
+    /// at its left edge on each of its rows. If `pad` is set, rows too short
+    /// to reach the edge are padded out to it. Returns whether there was any
+    /// text to delete.
+    fn take_block_selection(&mut self, pad: bool) -> bool {
+        let (first, last, left, right) = match self.block() {
+            Some(block) => block,
+            None => return false,
+        };
+        let (cursor_row, _) = self.cursor_position();
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(first, last + 1);
+        while self.rows.len() <= last {
+            self.rows.push(Row::empty());
+        }
+        let mut deleted = false;
+        let mut cursors = Vec::new();
+        for r in first..last + 1 {
+            let (start, end) = self.block_cols(r, left, right, pad);
+            if start < end {
+                let row = &mut self.rows[r];
+                let (from, to) = (row.byte_index(start), row.byte_index(end));
+                row.content.drain(from..to);
+                deleted = true;
+            }
+            cursors.push((r, start));
+        }
+        self.block_anchor = None;
+        let main = cursors.remove(cursor_row - first);
+        self.extra_cursors = cursors;
+        self.set_cursor_position(main.0, main.1);
+        self.end_edit(EditKind::Other);
+        deleted
+    }
+
+    /// Inserts the lines of `text` as a block, one to a row from the cursor
+    /// down, starting at the cursor's rendered column.
+    fn insert_block(&mut self, text: &str) {
+        let (row, _) = self.cursor_position();
+        let x = self.render_col_of(self.cursor_position());
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(row, row + text.split('\n').count());
+        let mut end = None;
+        for (i, line) in text.split('\n').enumerate() {
+            while self.rows.len() <= row + i {
+                self.rows.push(Row::empty());
+            }
+            let (col, _) = self.block_cols(row + i, x, x, true);
+            let byte = self.rows[row + i].byte_index(col);
+            self.rows[row + i].content.insert_str(byte, line);
+            end = end.or(Some((row, col + line.chars().count())));
+        }
+        if let Some((row, col)) = end {
+            self.set_cursor_position(row, col);
+        }
+        self.end_edit(EditKind::Other);
+    }
+
+    /// Returns the selected text, or the text in the block selection, ending
+    /// the selection. Returns `None` if nothing is selected.
+    pub fn copy_selection(&mut self) -> Option<String> {
+        if let Some(text) = self.block_text() {
+            self.block_anchor = None;
+            self.copied_block = Some(text.clone());
+            return Some(text);
+        }
+        let text = self.selection().map(|(start, end)| self.text_between(start, end));
+        self.selection_anchor = None;
+        self.copied_block = None;
+        text
+    }
+
+    /// Deletes the selected text, or the text in the block selection, and
+    /// returns it. Returns `None` if nothing is selected.
+    pub fn cut_selection(&mut self) -> Option<String> {
+        if let Some(text) = self.block_text() {
+            self.take_block_selection(false);
+            self.extra_cursors.clear();
+            self.copied_block = Some(text.clone());
+            return Some(text);
+        }
+        let (start, end) = match self.selection() {
+            Some(range) => range,
+            None => return None,
+        };
+        let text = self.text_between(start, end);
+        self.selection_anchor = None;
+        self.copied_block = None;
+        self.replace_range(start, end, "");
+        Some(text)
+    }
+
+    /// Pastes `text` at every cursor, or as a block if it is the block last
+    /// copied, replacing the text in any block selection.
+    pub fn paste(&mut self, text: &str) {
+        self.pasted = None;
+        if self.copied_block.as_ref().map_or(true, |block| block != text) {
+            let single = self.extra_cursors.is_empty() && self.block_anchor.is_none();
+            let start = self.clamp_position(self.cursor_position());
+            self.insert_str(text);
+            if single {
+                self.pasted = Some((start, self.cursor_position()));
+            }
+            return;
+        }
+        self.begin_edit(EditKind::Other);
+        self.take_block_selection(false);
+        self.extra_cursors.clear();
+        self.insert_block(text);
+        self.end_edit(EditKind::Other);
+    }
+
+    /// Swaps the text just pasted for `text`, as long as the cursor is still
+    /// at its end. Returns false if there is no paste to swap.
+    pub fn replace_paste(&mut self, text: &str) -> bool {
+        let (start, end) = match self.pasted {
+            Some(pasted) if pasted.1 == self.cursor_position() && self.extra_cursors.is_empty() => pasted,
+            _ => return false,
+        };
+        let end = self.replace_range(start, end, &text.replace("\r\n", "\n"));
+        self.pasted = Some((start, end));
+        true
+    }
+
+    /// Runs `op` with each cursor in turn as the main one, from the last in
+    /// the buffer to the first, then merges any cursors that end up in the
+    /// same place.
+    fn at_each_cursor<F: FnMut(&mut Editor)>(&mut self, mut op: F) {
+        if self.extra_cursors.is_empty() {
+            return op(self);
+        }
+        let main = self.clamp_position(self.cursor_position());
+        let mut cursors: Vec<(usize, usize)> = self.extra_cursors.iter()
+            .map(|&pos| self.clamp_position(pos))
+            .collect();
+        cursors.push(main);
+        cursors.sort();
+        cursors.dedup();
+
+        // Edits only change the text at and after the cursor they are made
+        // at, so the cursors already done are kept as distances from the end
+        // of the buffer and of their row, which earlier edits don't change
+        let row_len = |editor: &Editor, row: usize| editor.rows.get(row).map_or(0, |r| r.len());
+        let mut done = Vec::new();
+        let mut main_index = 0;
+        for &pos in cursors.iter().rev() {
+            self.set_cursor_position(pos.0, pos.1);
+            op(self);
+            let (row, col) = self.clamp_position(self.cursor_position());
+            if pos == main {
+                main_index = done.len();
+            }
+            done.push((self.rows.len() - row, row_len(self, row) - col));
+        }
+
+        let mut cursors: Vec<(usize, usize)> = done.iter()
+            .map(|&(rows_left, cols_left)| {
+                let row = self.rows.len().saturating_sub(rows_left);
+                (row, row_len(self, row).saturating_sub(cols_left))
+            })
+            .collect();
+        let main = cursors.remove(main_index);
+        cursors.sort();
+        cursors.dedup();
+        cursors.retain(|&pos| pos != main);
+        self.extra_cursors = cursors;
+        self.set_cursor_position(main.0, main.1);
+    }
+
+    /// Returns the rendered columns of the extra cursors on row `row_idx`.
+    fn extra_cursor_cols(&self, row_idx: usize) -> Vec<usize> {
+        let row = match self.rows.get(row_idx) {
+            Some(row) => row,
+            None => return vec![],
+        };
+        self.extra_cursors.iter()
+            .filter(|&&(r, _)| r == row_idx)
+            .map(|&(_, c)| row.render_col(cmp::min(c, row.len()), self.tab_width()))
+            .collect()
+    }
+
+    /// Returns how many cursors there are, counting the main one.
+    pub fn cursor_count(&self) -> usize {
+        self.extra_cursors.len() + 1
+    }
+
+    /// Adds a cursor on the row above the topmost cursor, or below the
+    /// bottommost one, in the main cursor's column. Returns false if there
+    /// is no row there.
+    pub fn add_cursor(&mut self, dir: CursorDirection) -> bool {
+        let main = self.cursor_position();
+        let mut cursors = self.extra_cursors.clone();
+        cursors.push(main);
+        let row = if dir == CursorDirection::Up {
+            match cursors.iter().min() {
+                Some(&(row, _)) if row > 0 => row - 1,
+                _ => return false,
+            }
+        } else {
+            match cursors.iter().max() {
+                Some(&(row, _)) if row + 1 < self.rows.len() => row + 1,
+                _ => return false,
+            }
+        };
+        let col = self.clamp_col(row, main.1);
+        self.extra_cursors.push((row, col));
+        true
+    }
+
+    /// Returns the start and end of the word `pos` is in or at the end of.
+    fn word_at(&self, pos: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let row = match self.rows.get(pos.0) {
+            Some(row) => row,
+            None => return None,
+        };
+        let chars: Vec<char> = row.content.chars().collect();
+        words::word_spans(&chars, false).into_iter()
+            .find(|&(start, end)| start <= pos.1 && pos.1 <= end)
+            .map(|(start, end)| ((pos.0, start), (pos.0, end)))
+    }
+
+    /// Adds a cursor at the next occurrence of the selected text, or of the
+    /// word the main cursor is in, after the one the last cursor was added
+    /// at. The new cursor is put in the same place within the occurrence as
+    /// the main one. Returns false if there is no other occurrence.
+    pub fn add_cursor_at_next_occurrence(&mut self) -> bool {
+        let main = self.cursor_position();
+        let mut options = SearchOptions::new();
+        let (start, end) = match self.selection() {
+            Some((start, end)) if start != end => (start, end),
+            _ => {
+                options.whole_word = true;
+                match self.word_at(main) {
+                    Some(word) => word,
+                    None => return false,
+                }
+            }
+        };
+        let search = match Search::new(&self.text_between(start, end), options) {
+            Ok(search) => search,
+            Err(_) => return false,
+        };
+        let within = self.offset_of(main) - self.offset_of(start);
+
+        let last = self.extra_cursors.last().cloned().unwrap_or(main);
+        let last_start = self.position_of(self.offset_of(last) - within);
+        let found = self.position_after(last_start)
+            .and_then(|from| self.find_next(&search, from))
+            .or_else(|| self.find_next(&search, (0, 0)));
+        let pos = match found {
+            Some((found_start, _)) => self.position_of(self.offset_of(found_start) + within),
+            None => return false,
+        };
+        if pos == main || self.extra_cursors.contains(&pos) {
+            return false;
+        }
+        self.extra_cursors.push(pos);
+        true
+    }
+
+    /// Removes the cursors besides the main one, returning whether there
+    /// were any.
+    pub fn clear_cursors(&mut self) -> bool {
+        let had_cursors = !self.extra_cursors.is_empty();
+        self.extra_cursors.clear();
+        had_cursors
+    }
+
+    /// Returns the part of the word before the cursor.
+    fn completion_prefix(&self) -> String {
+        let (row, col) = self.cursor_position();
+        match self.word_at((row, col)) {
+            Some(((_, start), _)) if start < col => {
+                self.rows[row].content.chars().skip(start).take(col - start).collect()
+            },
+            _ => String::new(),
+        }
+    }
+
+    /// Collects the words that start with `prefix` from the buffer, which is
+    /// the only one open, ranked by how near to the cursor's line they are
+    /// and then by how often they appear. The word under the cursor is left
+    /// out.
+    fn completions(&self, prefix: &str) -> Vec<String> {
+        // Each word's distance from the cursor in lines, and its count
+        let mut found: HashMap<String, (usize, usize)> = HashMap::new();
+        let cursor = self.cursor_position();
+        let current = self.word_at(cursor);
+        for (row_idx, row) in self.rows.iter().enumerate() {
+            let chars: Vec<char> = row.content.chars().collect();
+            for (start, end) in words::word_spans(&chars, false) {
+                // The word being typed doesn't complete itself
+                if current == Some(((row_idx, start), (row_idx, end))) {
+                    continue;
+                }
+                let word: String = chars[start..end].iter().cloned().collect();
+                if word.starts_with(prefix) && word != prefix {
+                    let distance = if row_idx < cursor.0 { cursor.0 - row_idx } else { row_idx - cursor.0 };
+                    let entry = found.entry(word).or_insert((distance, 0));
+                    entry.0 = cmp::min(entry.0, distance);
+                    entry.1 += 1;
+                }
+            }
+        }
+        let mut ranked: Vec<(String, (usize, usize))> = found.into_iter().collect();
+        ranked.sort_by(|&(ref a, (a_distance, a_count)), &(ref b, (b_distance, b_count))| {
+            (a_distance, b_count, a).cmp(&(b_distance, a_count, b))
+        });
+        ranked.into_iter().map(|(word, _)| word).collect()
+    }
+
+    /// Opens the completion popup with the words from the buffer that
+    /// complete the word before the cursor. Returns false, leaving the popup
+    /// closed, if there are none.
+    pub fn start_completion(&mut self) -> bool {
+        let prefix = self.completion_prefix();
+        let candidates = if prefix.is_empty() { vec![] } else { self.completions(&prefix) };
+        if candidates.is_empty() {
+            self.completion = None;
+            return false;
+        }
+        self.completion = Some(Completion { prefix: prefix, candidates: candidates, selected: 0 });
+        true
+    }
+
+    /// Narrows down the completions after the word before the cursor has
+    /// changed, closing the popup if none are left.
+    pub fn update_completion(&mut self) {
+        if self.completion.is_some() {
+            self.start_completion();
+        }
+    }
+
+    pub fn is_completing(&self) -> bool {
+        self.completion.is_some()
+    }
+
+    /// Highlights the previous or next completion, wrapping around at the
+    /// ends of the list.
+    pub fn select_completion(&mut self, dir: CursorDirection) {
+        if let Some(ref mut completion) = self.completion {
+            let count = completion.candidates.len();
+            completion.selected = match dir {
+                CursorDirection::Up => (completion.selected + count - 1) % count,
+                CursorDirection::Down => (completion.selected + 1) % count,
+                _ => completion.selected,
+            };
+        }
+    }
+
+    /// Completes the word before the cursor with the highlighted completion
+    /// and closes the popup.
+    pub fn accept_completion(&mut self) {
+        if let Some(completion) = self.completion.take() {
+            let word = &completion.candidates[completion.selected];
+            self.insert_str(&word[completion.prefix.len()..]);
+        }
+    }
+
+    pub fn cancel_completion(&mut self) {
+        self.completion = None;
+    }
+
+    pub fn empty_status(&mut self) {
+        self.status_message = None
+    }
+
+    pub fn display_status<S: AsRef<str>>(&mut self, status: S) {
+        self.status_message = Some(status.as_ref().to_owned());
+    }
+
+    /// Inserts `text` at every cursor as it is, without indenting or pairing
+    /// anything.
+    pub fn insert_str<S: AsRef<str>>(&mut self, text: S) {
+        let text = text.as_ref().replace("\r\n", "\n");
+        self.begin_edit(EditKind::Other);
+        self.take_block_selection(true);
+        self.at_each_cursor(|editor| {
+            let cursor = editor.clamp_position(editor.cursor_position());
+            editor.replace_range(cursor, cursor, &text);
+        });
+        self.end_edit(EditKind::Other);
+    }
+
+    /// Inserts a tab at every cursor, or the spaces up to the next tab stop
+    /// if tabs are being expanded.
+    pub fn insert_tab(&mut self) {
+        self.begin_edit(EditKind::Insert);
+        self.take_block_selection(true);
+        self.at_each_cursor(Editor::tab_at_cursor);
+        self.end_edit(EditKind::Insert);
+    }
+
+    fn tab_at_cursor(&mut self) {
+        if !self.config.expand_tabs {
+            return self.put_char(TAB);
+        }
+        let (file_row, file_col) = self.cursor_position();
+        let render_col = match self.rows.get(file_row) {
+            Some(row) => row.render_col(file_col, self.tab_width()),
+            None => 0,
+        };
+        let tab_width = self.tab_width();
+        self.begin_edit(EditKind::Insert);
+        for _ in 0..(tab_width - render_col % tab_width) {
+            self.put_char(' ');
+        }
+        self.end_edit(EditKind::Insert);
+    }
+
+    /// Inserts a character typed by the user at every cursor, pairing
+    /// brackets and quotes and lining up closing brackets as configured.
+    pub fn insert_char(&mut self, c: char) {
+        self.begin_edit(EditKind::Insert);
+        self.take_block_selection(true);
+        self.at_each_cursor(|editor| editor.type_char(c));
+        self.end_edit(EditKind::Insert);
+    }
+
+    fn type_char(&mut self, c: char) {
+        let (file_row, file_col) = self.cursor_position();
+
+        self.begin_edit(EditKind::Insert);
+        let at_cursor = self.char_at(file_row, file_col);
+        if self.config.auto_pair && at_cursor == Some(c) &&
+            self.auto_pairs().iter().any(|&(_, close)| close == c) {
+            // Type over the closing half of a pair
+            self.set_cursor_position(file_row, file_col + 1);
+        } else if let Some(close) = self.closing_pair(c, file_row, file_col) {
+            self.put_char(c);
+            self.put_char(close);
+            self.set_cursor_position(file_row, file_col + 1);
+        } else {
+            self.put_char(c);
+            let only_indented = self.rows[file_row].content.chars().take(file_col).all(|c| c == ' ' || c == TAB);
+            if self.config.auto_indent && self.config.smart_indent && only_indented &&
+                BRACKET_PAIRS.iter().any(|&(_, close)| close == c) {
+                self.align_closing_bracket(file_row, file_col);
+            }
+        }
+        self.end_edit(EditKind::Insert);
+    }
+
+    /// Inserts a character at the cursor as it is.
+    fn put_char(&mut self, c: char) {
+        let (file_row, file_col) = self.cursor_position();
+
+        self.begin_edit(EditKind::Insert);
+        self.touch_rows(file_row, file_row + 1);
+        if file_row >= self.rows.len() {
+            while self.rows.len() <= file_row {
+                self.rows.push(Row::empty());
+            }
+        }
+
+        self.rows[file_row].insert_char(file_col, c);
+        self.set_cursor_position(file_row, file_col + 1);
+
+        self.end_edit(EditKind::Insert);
+    }
+
+    /// Returns the pairs of characters auto-pairing types together.
+    fn auto_pairs(&self) -> Vec<(char, char)> {
+        syntax::auto_pairs(self.syntax_highlight.as_ref().map(|rule| &rule.name[..]))
+    }
+
+    /// Returns the character to type after `c` at `(row, col)` if `c` opens a
+    /// pair that should be closed automatically.
+    fn closing_pair(&self, c: char, row: usize, col: usize) -> Option<char> {
+        if !self.config.auto_pair {
+            return None;
+        }
+        let pairs = self.auto_pairs();
+        let close = match pairs.iter().find(|&&(open, _)| open == c) {
+            Some(&(_, close)) => close,
+            None => return None,
+        };
+        // Only close pairs before whitespace or a closing bracket, so typing
+        // in front of a word doesn't leave a stray character behind
+        let fits_before = |next: char| {
+            next.is_whitespace() || pairs.iter().any(|&(open, close)| open != close && close == next)
+        };
+        if !self.char_at(row, col).map_or(true, fits_before) {
+            return None;
+        }
+        // A quote after a word is probably an apostrophe or a closing quote
+        if c == close && col > 0 {
+            let prev = self.char_at(row, col - 1);
+            if prev.map_or(false, |p| p.is_alphanumeric() || p == '_' || p == c) {
+                return None;
+            }
+        }
+        Some(close)
+    }
+
+    /// Toggles auto-pairing of brackets and quotes, returning whether it is
+    /// now on.
+    pub fn toggle_auto_pair(&mut self) -> bool {
+        self.config.auto_pair = !self.config.auto_pair;
+        self.config.auto_pair
+    }
+
+    /// Gives a closing bracket typed at the start of a line the indentation
+    /// of the line with its opening bracket.
+    fn align_closing_bracket(&mut self, row: usize, col: usize) {
+        let indent = match self.find_matching_bracket(row, col) {
+            Some((open_row, _)) => leading_whitespace(&self.rows[open_row].content).to_owned(),
+            None => return,
+        };
+        let content = {
+            let row = &self.rows[row];
+            format!("{}{}", indent, &row.content[row.byte_index(col)..])
+        };
+        self.touch_rows(row, row + 1);
+        self.rows[row].content = content;
+        let new_col = indent.chars().count() + 1;
+        self.set_cursor_position(row, new_col);
+    }
+
+    /// Returns the text making up one level of indentation, using tabs or
+    /// spaces to match the existing `indent` when there is any.
+    fn indent_unit(&self, indent: &str) -> String {
+        let spaces = if indent.is_empty() { self.config.expand_tabs } else { !indent.contains(TAB) };
+        if spaces {
+            " ".repeat(self.tab_width())
+        } else {
+            TAB.to_string()
+        }
+    }
+
+    /// Returns whether a new line after `text` should be indented one level
+    /// further than it, because it ends in an opening bracket or another of
+    /// the filetype's block-opening tokens.
+    fn opens_indent(&self, text: &str) -> bool {
+        let text = text.trim();
+        let ends_with = |token: &str| {
+            if !text.ends_with(token) {
+                return false;
+            }
+            // Keywords must be whole words
+            let is_word = |c: char| c.is_alphanumeric() || c == '_';
+            !token.chars().next().map_or(false, &is_word) ||
+                !text[..text.len() - token.len()].chars().last().map_or(false, &is_word)
+        };
+        match self.syntax_highlight {
+            Some(ref rule) => rule.indent_after.iter().any(|token| ends_with(token)),
+            None => BRACKET_PAIRS.iter().any(|&(open, _)| ends_with(&open.to_string())),
+        }
+    }
+
+    /// Indents the selected rows, or the cursor's row, by one level.
+    pub fn indent_lines(&mut self) {
+        self.shift_lines(true);
+    }
+
+    /// Removes one level of indentation from the selected rows, or the
+    /// cursor's row.
+    pub fn outdent_lines(&mut self) {
+        self.shift_lines(false);
+    }
+
+    fn shift_lines(&mut self, indent: bool) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let cursor = self.cursor_position();
+        let (first, last) = self.selected_rows();
+        let last = cmp::min(last, self.rows.len() - 1);
+        let unit = self.indent_unit("");
+        let tab_width = self.tab_width();
+
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(first, last + 1);
+        let mut shifts = vec![];
+        for row in &mut self.rows[first..last+1] {
+            let shift = if indent {
+                if row.content.is_empty() {
+                    0
+                } else {
+                    row.content.insert_str(0, &unit);
+                    unit.chars().count() as isize
+                }
+            } else {
+                let removed = if row.content.starts_with(TAB) {
+                    1
+                } else {
+                    row.content.chars().take(tab_width).take_while(|&c| c == ' ').count()
+                };
+                row.content = row.content[removed..].to_owned();
+                -(removed as isize)
+            };
+            shifts.push(shift);
         }
+
+        // Keep the cursor and the selection on the same text
+        let shifted = |pos: (usize, usize)| {
+            if pos.0 < first || pos.0 > last {
+                return pos;
+            }
+            let col = cmp::max(pos.1 as isize + shifts[pos.0 - first], 0) as usize;
+            (pos.0, col)
+        };
+        self.selection_anchor = self.selection_anchor.map(|anchor| shifted(self.clamp_position(anchor)));
+        let (row, col) = shifted(cursor);
+        self.set_cursor_position(row, col);
+        self.end_edit(EditKind::Other);
     }
 
-    pub fn page_cursor(&mut self, dir: CursorDirection) {
-        if dir == CursorDirection::Up && !self.top_edge() {
-            self.cursor_y = 0;
-        } else if dir == CursorDirection::Down && !self.bottom_edge() {
-            self.cursor_y = self.screen_rows - 1;
+    /// Returns the first and last rows touched by the selection, or the
+    /// cursor's row if there is none. A selection ending at the start of a
+    /// row doesn't include that row.
+    fn selected_rows(&self) -> (usize, usize) {
+        let cursor = self.cursor_position();
+        let (start, end) = self.selection().unwrap_or((cursor, cursor));
+        let last = if end.0 > start.0 && end.1 == 0 { end.0 - 1 } else { end.0 };
+        (start.0, last)
+    }
+
+    /// Returns the text between two positions, with rows separated by
+    /// newlines.
+    fn text_between(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        let text = self.buffer_text();
+        text[self.offset_of(start)..self.offset_of(end)].to_owned()
+    }
+
+    /// Inserts a copy of the selected text after it and selects the copy, or
+    /// duplicates the cursor's row if nothing is selected.
+    pub fn duplicate(&mut self) {
+        let cursor = self.cursor_position();
+        match self.selection() {
+            Some((start, end)) if start != end => {
+                let text = self.text_between(start, end);
+                self.begin_edit(EditKind::Other);
+                self.replace_range(end, end, &text);
+                self.selection_anchor = Some(end);
+                self.end_edit(EditKind::Other);
+            },
+            _ => {
+                if cursor.0 >= self.rows.len() {
+                    return;
+                }
+                self.begin_edit(EditKind::Other);
+                self.touch_rows(cursor.0 + 1, cursor.0 + 1);
+                let copy = Row::with_content(&self.rows[cursor.0].content);
+                self.rows.insert(cursor.0 + 1, copy);
+                self.set_cursor_position(cursor.0 + 1, cursor.1);
+                self.end_edit(EditKind::Other);
+            }
         }
-        for _ in 0..self.screen_rows {
-            self.move_cursor(dir);
+    }
+
+    /// Moves the selected rows, or the cursor's row, up or down past the
+    /// neighbouring row.
+    pub fn move_lines(&mut self, dir: CursorDirection) {
+        let (first, last) = self.selected_rows();
+        if last >= self.rows.len() {
+            return;
         }
+        self.begin_edit(EditKind::Other);
+        let moved_down = match dir {
+            CursorDirection::Up if first > 0 => {
+                self.touch_rows(first - 1, last + 1);
+                let row = self.rows.remove(first - 1);
+                self.rows.insert(last, row);
+                false
+            },
+            CursorDirection::Down if last + 1 < self.rows.len() => {
+                self.touch_rows(first, last + 2);
+                let row = self.rows.remove(last + 1);
+                self.rows.insert(first, row);
+                true
+            },
+            _ => {
+                self.end_edit(EditKind::Other);
+                return;
+            }
+        };
+        let shifted = |(row, col): (usize, usize)| if moved_down { (row + 1, col) } else { (row - 1, col) };
+        self.selection_anchor = self.selection_anchor.map(|anchor| shifted(self.clamp_position(anchor)));
+        let (row, col) = shifted(self.cursor_position());
+        self.set_cursor_position(row, col);
+        self.end_edit(EditKind::Other);
     }
 
-    pub fn backspace(&mut self) {
-        debug!("backspace");
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
-        if file_row >= self.rows.len() || (file_col == 0 && file_row == 0) {
+    /// Joins the cursor's row with the next one, or all the selected rows
+    /// together, replacing the indentation and trailing whitespace where they
+    /// meet with a single space.
+    pub fn join_lines(&mut self) {
+        let (first, last) = self.selected_rows();
+        let last = if last == first { first + 1 } else { last };
+        if last >= self.rows.len() {
             return;
         }
-        if file_col == 0 {
-            // Append to the prior row, then delete the current row
-            let content = self.rows[file_row].content.clone();
-            let prior_row_len = self.rows[file_row-1].content.len();
-            self.rows[file_row-1].push_str(content);
-            self.rows.remove(file_row);
-            if self.cursor_y == 0 {
-                self.row_offset -= 1;
-            } else {
-                self.cursor_y -= 1;
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(first, last + 1);
+        let mut col = 0;
+        for _ in first..last {
+            col = self.rows[first].trailing_whitespace_start();
+            self.rows[first].truncate(col);
+            let next = self.rows.remove(first + 1);
+            let tail = &next.content[leading_whitespace(&next.content).len()..];
+            if col > 0 && !tail.is_empty() {
+                self.rows[first].push_str(" ".to_owned());
+                col += 1;
             }
-            self.cursor_x = prior_row_len;
-            if self.cursor_x >= self.screen_cols {
-                let shift = (self.screen_cols - self.cursor_x) + 1;
-                self.cursor_x -= shift;
-                self.col_offset += shift;
+            self.rows[first].push_str(tail.to_owned());
+        }
+        self.selection_anchor = None;
+        self.set_cursor_position(first, col);
+        self.end_edit(EditKind::Other);
+    }
+
+    /// Deletes the selected rows, or the cursor's row, entirely.
+    pub fn delete_lines(&mut self) {
+        let (first, last) = self.selected_rows();
+        if first >= self.rows.len() {
+            return;
+        }
+        let last = cmp::min(last, self.rows.len() - 1);
+        let (_, file_col) = self.cursor_position();
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(first, last + 1);
+        self.rows.drain(first..last+1);
+        self.selection_anchor = None;
+        let col = self.clamp_col(first, file_col);
+        self.set_cursor_position(first, col);
+        self.end_edit(EditKind::Other);
+    }
+
+    /// Sorts the selected rows, dropping duplicates if `unique` is set, and
+    /// selects them. Returns how many rows were dropped, or `None` if fewer
+    /// than two rows are selected.
+    pub fn sort_lines(&mut self, unique: bool) -> Option<usize> {
+        let (first, last) = self.selected_rows();
+        let last = cmp::min(last, self.rows.len().saturating_sub(1));
+        if self.selection_anchor.is_none() || first >= last {
+            return None;
+        }
+        let mut lines: Vec<String> = self.rows[first..last+1].iter().map(|row| row.content.clone()).collect();
+        let count = lines.len();
+        lines.sort();
+        if unique {
+            lines.dedup();
+        }
+        let new_last = first + lines.len() - 1;
+        let new_rows: Vec<Row> = lines.iter().map(|line| Row::with_content(line)).collect();
+
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(first, last + 1);
+        let following = self.rows.split_off(last + 1);
+        self.rows.truncate(first);
+        self.rows.extend(new_rows);
+        self.rows.extend(following);
+        self.selection_anchor = Some((first, 0));
+        let end_col = self.rows[new_last].len();
+        self.set_cursor_position(new_last, end_col);
+        self.end_edit(EditKind::Other);
+        Some(count - lines.len())
+    }
+
+    /// Comments out the selected rows, or the cursor's row, or uncomments
+    /// them if they are all comments already. Languages with only block
+    /// comments get one around the whole range, unless it holds the end of
+    /// another block comment, as they don't nest. Returns false if the
+    /// filetype has no comments.
+    pub fn toggle_comment(&mut self) -> bool {
+        let (line_comment, block_comment) = match self.syntax_highlight {
+            Some(ref rule) => {
+                (rule.single_line_comment_start.iter().min().cloned(),
+                 (rule.multi_line_comment_start.clone(), rule.multi_line_comment_end.clone()))
+            },
+            None => return false,
+        };
+        if line_comment.is_none() && block_comment.0.is_empty() {
+            return false;
+        }
+        let (first, last) = self.selected_rows();
+        if first >= self.rows.len() {
+            return true;
+        }
+        let last = cmp::min(last, self.rows.len() - 1);
+        // Blank rows are left alone
+        let targets: Vec<usize> = (first..last+1).filter(|&r| !self.rows[r].content.trim().is_empty()).collect();
+        if targets.is_empty() {
+            return true;
+        }
+        let (top, bottom) = (targets[0], targets[targets.len() - 1]);
+        let block_commented = {
+            let (ref start, ref end) = block_comment;
+            let rows: Vec<&str> = self.rows[top..bottom+1].iter().map(|row| row.content.as_str()).collect();
+            let text = rows.join("\n");
+            let text = text.trim();
+            let commented = text.len() >= start.len() + end.len() && text.starts_with(start.as_str()) &&
+                text.ends_with(end.as_str());
+            let inner = if commented { &text[start.len()..text.len() - end.len()] } else { text };
+            if line_comment.is_none() && inner.contains(end.as_str()) {
+                return true;
             }
-        } else {
-            // Just drop the char from the row
-            self.rows[file_row].backspace(file_col);
-            if self.left_edge() && self.col_offset > 0 {
-                self.col_offset -= 1;
-            } else if !self.left_edge() {
-                self.cursor_x -= 1;
+            commented
+        };
+
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(top, bottom + 1);
+        // Each change as the row, the column it was made at and how many
+        // characters were inserted or removed
+        let mut changes: Vec<(usize, usize, isize)> = vec![];
+        match line_comment {
+            Some(token) => {
+                let commented = targets.iter().all(|&r| {
+                    let content = &self.rows[r].content;
+                    content[leading_whitespace(content).len()..].starts_with(&token)
+                });
+                if commented {
+                    for &r in &targets {
+                        let (indent, removed) = uncomment_start(&mut self.rows[r].content, &token);
+                        changes.push((r, indent, -(removed as isize)));
+                    }
+                } else {
+                    let indent = targets.iter()
+                        .map(|&r| leading_whitespace(&self.rows[r].content).len())
+                        .min()
+                        .unwrap_or(0);
+                    let prefix = format!("{} ", token);
+                    for &r in &targets {
+                        self.rows[r].content.insert_str(indent, &prefix);
+                        changes.push((r, indent, prefix.chars().count() as isize));
+                    }
+                }
+            },
+            None => {
+                let (start, end) = block_comment;
+                let bottom_len = self.rows[bottom].trailing_whitespace_start();
+                self.rows[bottom].truncate(bottom_len);
+                if block_commented {
+                    let content = &mut self.rows[bottom].content;
+                    let len = content.len() - end.len();
+                    content.truncate(len);
+                    if content.ends_with(' ') {
+                        content.pop();
+                    }
+                    let (indent, removed) = uncomment_start(&mut self.rows[top].content, &start);
+                    changes.push((top, indent, -(removed as isize)));
+                } else {
+                    self.rows[bottom].push_str(format!(" {}", end));
+                    let indent = leading_whitespace(&self.rows[top].content).len();
+                    let prefix = format!("{} ", start);
+                    self.rows[top].content.insert_str(indent, &prefix);
+                    changes.push((top, indent, prefix.chars().count() as isize));
+                }
             }
         }
 
-        self.modified = true;
-        debug!("number of rows: {}", self.rows.len());
+        // Keep the cursor and the selection on the same text
+        let shifted = |pos: (usize, usize)| {
+            match changes.iter().find(|&&(row, col, _)| row == pos.0 && pos.1 >= col) {
+                Some(&(_, col, change)) => (pos.0, cmp::max(pos.1 as isize + change, col as isize) as usize),
+                None => pos,
+            }
+        };
+        self.selection_anchor = self.selection_anchor.map(&shifted);
+        let (row, col) = shifted(self.cursor_position());
+        let col = self.clamp_col(row, col);
+        self.set_cursor_position(row, col);
+        self.end_edit(EditKind::Other);
+        true
+    }
+
+    /// Returns the single-line comment markers of the file type.
+    fn comment_markers(&self) -> Vec<String> {
+        self.syntax_highlight.as_ref()
+            .map_or(vec![], |rule| rule.single_line_comment_start.iter().cloned().collect())
     }
 
-    pub fn backspace_word(&mut self) {
-        if let Some(num_backspaces) = self.find_previous_non_alphanumeric_char_index() {
-            for _ in 0..num_backspaces {
-                self.backspace();
+    /// Returns whether rows `a` and `b` can be part of the same paragraph,
+    /// which is when neither is blank, they have the same comment marker and
+    /// `b` doesn't start a list item.
+    fn same_paragraph(&self, a: usize, b: usize, markers: &[String]) -> bool {
+        let (first, second) = (&self.rows[a].content, &self.rows[b].content);
+        let (first_len, second_len) = (line_prefix_len(first, markers), line_prefix_len(second, markers));
+        !first[first_len..].trim().is_empty() && !second[second_len..].trim().is_empty() &&
+            first[..first_len].trim() == second[..second_len].trim() &&
+            bullet_len(&second[second_len..]) == 0
+    }
+
+    /// Rewraps the words of rows `first` to `last` as a paragraph to fit
+    /// within the fill column. The first row's indentation, comment marker
+    /// and list bullet start the first line, and the lines after it are
+    /// lined up with the text after them.
+    fn fill_paragraph(&self, first: usize, last: usize, markers: &[String]) -> Vec<String> {
+        let head = &self.rows[first].content;
+        let prefix_len = line_prefix_len(head, markers);
+        let bullet = bullet_len(&head[prefix_len..]);
+        let first_prefix = head[..prefix_len + bullet].to_owned();
+        let rest_prefix = if bullet > 0 {
+            head[..prefix_len].to_owned() + &iter::repeat(' ').take(bullet).collect::<String>()
+        } else if last > first {
+            let next = &self.rows[first + 1].content;
+            next[..line_prefix_len(next, markers)].to_owned()
+        } else {
+            first_prefix.clone()
+        };
+
+        let mut words = Vec::new();
+        for r in first..last + 1 {
+            let content = &self.rows[r].content;
+            let mut start = line_prefix_len(content, markers);
+            if r == first {
+                start += bullet;
+            }
+            words.extend(content[start..].split_whitespace());
+        }
+
+        let width = |text: &str| Row::with_content(text).render_col(usize::max_value(), self.tab_width());
+        let mut lines = Vec::new();
+        let mut line = first_prefix.clone();
+        let mut empty = true;
+        for word in words {
+            if !empty && width(&line) + 1 + word.chars().count() > self.config.fill_column {
+                lines.push(line);
+                line = rest_prefix.clone();
+                empty = true;
             }
+            if !empty {
+                line.push(' ');
+            }
+            line.push_str(word);
+            empty = false;
         }
+        lines.push(line);
+        lines
     }
 
-    pub fn backspace_to_start_of_line(&mut self) {
-        // Simply delete from here to the beginning of the line
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
+    /// Rewraps the paragraph at the cursor, or every paragraph in the
+    /// selection, to fit within the fill column as one edit. Indentation,
+    /// comment markers and list bullets are kept. Returns false if there is
+    /// no paragraph to reflow.
+    pub fn reflow(&mut self) -> bool {
+        let markers = self.comment_markers();
+        let (mut first, mut last) = self.selected_rows();
+        last = cmp::min(last, self.rows.len().saturating_sub(1));
+        if first > last || first >= self.rows.len() {
+            return false;
+        }
+        if self.selection().is_none() {
+            let content = &self.rows[first].content;
+            if content[line_prefix_len(content, &markers)..].trim().is_empty() {
+                return false;
+            }
+            while first > 0 && self.same_paragraph(first - 1, first, &markers) {
+                first -= 1;
+            }
+            while last + 1 < self.rows.len() && self.same_paragraph(last, last + 1, &markers) {
+                last += 1;
+            }
+        }
 
-        if file_row < self.rows.len() {
-            for _ in 0..file_col {
-                self.backspace()
+        let mut lines = Vec::new();
+        let mut start = first;
+        while start <= last {
+            let content = &self.rows[start].content;
+            if content[line_prefix_len(content, &markers)..].trim().is_empty() {
+                // Leave blank lines between paragraphs alone
+                lines.push(content.clone());
+                start += 1;
+                continue;
             }
+            let mut end = start;
+            while end < last && self.same_paragraph(end, end + 1, &markers) {
+                end += 1;
+            }
+            lines.extend(self.fill_paragraph(start, end, &markers));
+            start = end + 1;
         }
-    }
 
-    pub fn empty_status(&mut self) {
-        self.status_message = None
+        let end = (last, self.rows[last].len());
+        self.selection_anchor = None;
+        self.replace_range((first, 0), end, &lines.join("\n"));
+        true
     }
 
-    pub fn display_status<S: AsRef<str>>(&mut self, status: S) {
-        self.status_message = Some(status.as_ref().to_owned());
+    /// Returns the start and end of the identifier, including any hyphens,
+    /// that `pos` is in or at the end of.
+    fn identifier_at(&self, pos: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let row = match self.rows.get(pos.0) {
+            Some(row) => row,
+            None => return None,
+        };
+        let chars: Vec<char> = row.content.chars().collect();
+        let is_part = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        let col = cmp::min(pos.1, chars.len());
+        let start = col - chars[..col].iter().rev().take_while(|&&c| is_part(c)).count();
+        let end = col + chars[col..].iter().take_while(|&&c| is_part(c)).count();
+        if start < end { Some(((pos.0, start), (pos.0, end))) } else { None }
     }
 
-    pub fn insert_str<S: AsRef<str>>(&mut self, text: S) {
-        for c in text.as_ref().chars() {
-            self.insert_char(c);
+    /// Converts the selected text, or the identifier at the cursor, to
+    /// `case`. Returns false if there is nothing to convert.
+    pub fn convert_case(&mut self, case: Case) -> bool {
+        let cursor = self.cursor_position();
+        let selected = match self.selection() {
+            Some((start, end)) if start < end => Some((start, end)),
+            _ => None,
+        };
+        let (start, end) = match selected.or_else(|| self.identifier_at(cursor)) {
+            Some(range) => range,
+            None => return false,
+        };
+        let text = self.text_between(start, end);
+        let converted = transform::convert_case(&text, case);
+        if converted == text {
+            return true;
         }
+        let end = self.replace_range(start, end, &converted);
+        if selected.is_some() {
+            self.select_range(start, end);
+        } else if converted.chars().count() == text.chars().count() {
+            self.set_cursor_position(cursor.0, cursor.1);
+        }
+        true
     }
 
-    pub fn insert_char(&mut self, c: char) {
-        let file_row = self.row_offset + self.cursor_y;
-        let file_col = self.col_offset + self.cursor_x;
+    /// Adds `delta` to the number under or after the cursor on its row, or
+    /// that many days to a `YYYY-MM-DD` date, leaving the cursor on its last
+    /// character. Returns false if there is no number.
+    pub fn increment_number(&mut self, delta: i64) -> bool {
+        let (row, col) = self.cursor_position();
+        let found = self.rows.get(row).and_then(|r| transform::increment(&r.content, col, delta));
+        match found {
+            Some((start, end, text)) => {
+                let (_, new_end) = self.replace_range((row, start), (row, end), &text);
+                self.set_cursor_position(row, new_end - 1);
+                true
+            },
+            None => false,
+        }
+    }
 
-        if file_row >= self.rows.len() {
-            while self.rows.len() <= file_row {
-                self.rows.push(Row::empty());
-            }
+    /// Swaps the characters either side of the cursor, or the two before it
+    /// at the end of a row, and moves the cursor past them. Returns false if
+    /// there aren't two characters to swap.
+    pub fn transpose_chars(&mut self) -> bool {
+        let (row, col) = self.cursor_position();
+        let len = self.rows.get(row).map_or(0, |r| r.len());
+        if len < 2 || col == 0 {
+            return false;
         }
+        let col = cmp::min(col, len - 1);
+        let chars: Vec<char> = self.rows[row].content.chars().collect();
+        let swapped: String = vec![chars[col], chars[col - 1]].into_iter().collect();
+        self.replace_range((row, col - 1), (row, col + 1), &swapped);
+        true
+    }
 
-        self.rows[file_row].insert_char(file_col, c);
-        if self.right_edge() {
-            self.col_offset += 1;
+    /// Swaps the word before the cursor with the one after it on the same
+    /// row, or with the one before it if it is the last, and moves the
+    /// cursor past them. Returns false if there aren't two words to swap.
+    pub fn transpose_words(&mut self) -> bool {
+        let (row, col) = self.cursor_position();
+        let chars: Vec<char> = match self.rows.get(row) {
+            Some(r) => r.content.chars().collect(),
+            None => return false,
+        };
+        let spans = words::word_spans(&chars, false);
+        let before = match spans.iter().rposition(|&(start, _)| start < col) {
+            Some(i) => i,
+            None => return false,
+        };
+        let (first, second) = if before + 1 < spans.len() {
+            (spans[before], spans[before + 1])
+        } else if before > 0 {
+            (spans[before - 1], spans[before])
         } else {
-            self.cursor_x += 1;
-        }
+            return false;
+        };
+        let text = |(start, end): (usize, usize)| chars[start..end].iter().cloned().collect::<String>();
+        let swapped = format!("{}{}{}", text(second), text((first.1, second.0)), text(first));
+        self.replace_range((row, first.0), (row, second.1), &swapped);
+        true
+    }
 
-        self.modified = true;
+    /// Returns whether there is a selection.
+    pub fn has_selection(&self) -> bool {
+        self.selection_anchor.is_some()
     }
 
     pub fn newline(&mut self) {
+        self.begin_edit(EditKind::Other);
+        self.at_each_cursor(Editor::break_line);
+        self.end_edit(EditKind::Other);
+    }
+
+    fn break_line(&mut self) {
         debug!("newline");
-        let file_row = self.row_offset + self.cursor_y;
-        let mut file_col = self.col_offset + self.cursor_x;
+        let (file_row, mut file_col) = self.cursor_position();
+
+        if file_row > self.rows.len() {
+            return;
+        }
+        self.begin_edit(EditKind::Other);
+        self.touch_rows(file_row, file_row + 1);
 
+        let mut new_col = 0;
         use std::cmp::Ordering::*;
         match file_row.cmp(&self.rows.len()) {
-            Greater => return,
+            Greater => unreachable!(),
             Equal => {
                 self.rows.push(Row::empty());
             },
             Less => {
-                if file_col >= self.rows[file_row].content.len() {
-                    file_col = self.rows[file_row].content.len();
+                if file_col >= self.rows[file_row].len() {
+                    file_col = self.rows[file_row].len();
                 }
 
                 if file_col == 0 {
@@ -589,22 +3295,839 @@ impl Editor {
                 } else {
                     // Split the current row in TWO!
                     let content = self.rows[file_row].content.clone();
-                    let (prior_row, new_row) = content.split_at(file_col);
-                    self.rows.insert(file_row+1, Row::with_content(new_row));
+                    let (prior_row, mut new_row) = content.split_at(self.rows[file_row].byte_index(file_col));
+                    let mut indent = String::new();
+                    let mut opens = false;
+                    if self.config.auto_indent {
+                        indent.push_str(leading_whitespace(prior_row));
+                        new_row = &new_row[leading_whitespace(new_row).len()..];
+                        opens = self.config.smart_indent && self.opens_indent(prior_row);
+                    }
+                    let mut inner = indent.clone();
+                    if opens {
+                        inner.push_str(&self.indent_unit(&indent));
+                    }
+                    new_col = inner.chars().count();
+                    let closes = match (prior_row.trim().chars().last(), new_row.chars().next()) {
+                        (Some(open), Some(close)) => BRACKET_PAIRS.contains(&(open, close)),
+                        _ => false,
+                    };
+                    if opens && closes {
+                        // Put the closing bracket on its own line below
+                        indent.push_str(new_row);
+                        self.rows.insert(file_row+1, Row::with_content(&indent));
+                    } else {
+                        inner.push_str(new_row);
+                    }
+                    self.rows.insert(file_row+1, Row::with_content(&inner));
                     self.rows[file_row].content = prior_row.to_owned();
                 }
             }
         }
         // Fix the cursor position
-        if self.bottom_edge() {
-            self.row_offset += 1;
-        } else {
-            self.cursor_y += 1;
+        self.set_cursor_position(file_row + 1, new_col);
+        self.end_edit(EditKind::Other);
+        debug!("number of rows: {}", self.rows.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use super::{CursorDirection, Editor, Row, BRACKET_SEARCH_ROWS};
+    use search::{Search, SearchOptions};
+    use syntax::SyntaxHighlightRule;
+
+    fn editor(lines: &[&str]) -> Editor {
+        let mut editor = Editor::new(24, 80);
+        for line in lines {
+            editor.rows.push(Row::with_content(line));
         }
+        editor
+    }
 
-        self.cursor_x = 0;
-        self.col_offset = 0;
-        self.modified = true;
-        debug!("number of rows: {}", self.rows.len());
+    fn contents(editor: &Editor) -> Vec<String> {
+        editor.rows.iter().map(|row| row.content.clone()).collect()
+    }
+
+    #[test]
+    fn word_motions_cross_lines() {
+        let mut e = editor(&["foo_bar(baz);", "", "  naïve qux"]);
+        e.cursor_to_right_word();
+        assert_eq!(e.cursor_position(), (0, 7));
+        e.cursor_to_right_word();
+        assert_eq!(e.cursor_position(), (0, 11));
+        e.cursor_to_right_word();
+        assert_eq!(e.cursor_position(), (2, 7));
+        e.cursor_to_right_word();
+        e.cursor_to_right_word();
+        assert_eq!(e.cursor_position(), (2, 11));
+        e.cursor_to_left_word();
+        e.cursor_to_left_word();
+        assert_eq!(e.cursor_position(), (2, 2));
+        e.cursor_to_left_word();
+        assert_eq!(e.cursor_position(), (0, 8));
+        e.cursor_to_left_word();
+        e.cursor_to_left_word();
+        assert_eq!(e.cursor_position(), (0, 0));
+    }
+
+    #[test]
+    fn subword_motions() {
+        let mut e = editor(&["parseHTTPHeader_value"]);
+        e.cursor_to_right_subword();
+        assert_eq!(e.cursor_position(), (0, 5));
+        e.cursor_to_right_subword();
+        assert_eq!(e.cursor_position(), (0, 9));
+        e.cursor_to_right_subword();
+        e.cursor_to_right_subword();
+        assert_eq!(e.cursor_position(), (0, 21));
+        e.cursor_to_left_subword();
+        assert_eq!(e.cursor_position(), (0, 16));
+        e.cursor_to_left_subword();
+        assert_eq!(e.cursor_position(), (0, 9));
+    }
+
+    #[test]
+    fn backspace_word_joins_lines() {
+        let mut e = editor(&["one two", "  three"]);
+        e.set_cursor_position(1, 2);
+        e.backspace_word();
+        assert_eq!(e.rows[0].content, "one three");
+        assert_eq!(e.cursor_position(), (0, 4));
+        e.backspace_word();
+        assert_eq!(e.rows[0].content, "three");
+    }
+
+    #[test]
+    fn undo_groups_runs_of_typing() {
+        let mut e = editor(&["abc"]);
+        e.set_cursor_position(0, 3);
+        e.insert_char('d');
+        e.insert_char('e');
+        e.backspace();
+        e.set_cursor_position(0, 0);
+        e.insert_char('x');
+        assert_eq!(contents(&e), vec!["xabcd"]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["abcd"]);
+        assert_eq!(e.cursor_position(), (0, 0));
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["abcde"]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["abc"]);
+        assert_eq!(e.cursor_position(), (0, 3));
+        assert!(!e.undo());
+        assert!(e.redo());
+        assert!(e.redo());
+        assert!(e.redo());
+        assert_eq!(contents(&e), vec!["xabcd"]);
+        assert_eq!(e.cursor_position(), (0, 1));
+        assert!(!e.redo());
+    }
+
+    #[test]
+    fn undo_groups_and_redo() {
+        let mut e = editor(&["one", "two"]);
+        e.set_cursor_position(0, 3);
+        e.begin_undo_group();
+        e.newline();
+        e.insert_char('x');
+        e.set_cursor_position(2, 0);
+        e.backspace();
+        e.end_undo_group();
+        assert_eq!(contents(&e), vec!["one", "xtwo"]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["one", "two"]);
+        assert!(!e.undo());
+
+        // An edit after undoing drops what could be redone
+        e.set_cursor_position(1, 3);
+        e.insert_char('!');
+        assert!(!e.redo());
+
+        // An edit that changes nothing isn't kept
+        e.set_cursor_position(0, 0);
+        e.backspace();
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["one", "two"]);
+        assert!(!e.undo());
+    }
+
+    #[test]
+    fn backspace_to_start_of_line_leaves_pairs_alone() {
+        let mut e = editor(&["foo()"]);
+        e.config.auto_pair = true;
+        e.set_cursor_position(0, 4);
+        assert_eq!(e.backspace_to_start_of_line(), "foo(");
+        assert_eq!(contents(&e), vec![")"]);
+        assert_eq!(e.cursor_position(), (0, 0));
+
+        e.set_cursor_position(0, 0);
+        e.insert_char('(');
+        e.insert_char('x');
+        e.backspace();
+        assert_eq!(contents(&e), vec!["())"]);
+        e.backspace();
+        assert_eq!(contents(&e), vec![")"]);
+    }
+
+    #[test]
+    fn edits_at_each_cursor() {
+        let mut e = editor(&["abcd"]);
+        e.set_cursor_position(0, 1);
+        e.extra_cursors = vec![(0, 3)];
+        e.insert_char('x');
+        assert_eq!(contents(&e), vec!["axbcxd"]);
+        assert_eq!(e.cursor_position(), (0, 2));
+        assert_eq!(e.extra_cursors, vec![(0, 5)]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["abcd"]);
+
+        e.set_cursor_position(0, 1);
+        e.extra_cursors = vec![(0, 3)];
+        e.newline();
+        assert_eq!(contents(&e), vec!["a", "bc", "d"]);
+        assert_eq!(e.cursor_position(), (1, 0));
+        assert_eq!(e.extra_cursors, vec![(2, 0)]);
+    }
+
+    #[test]
+    fn backspace_at_each_cursor_joins_rows() {
+        let mut e = editor(&["ab", "cd", "ef"]);
+        e.set_cursor_position(1, 0);
+        e.extra_cursors = vec![(2, 0)];
+        e.backspace();
+        assert_eq!(contents(&e), vec!["abcdef"]);
+        assert_eq!(e.cursor_position(), (0, 2));
+        assert_eq!(e.extra_cursors, vec![(0, 4)]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["ab", "cd", "ef"]);
+    }
+
+    #[test]
+    fn colliding_cursors_are_merged() {
+        let mut e = editor(&["ab", "cd"]);
+        e.set_cursor_position(1, 0);
+        e.extra_cursors = vec![(1, 1)];
+        e.backspace();
+        assert_eq!(contents(&e), vec!["abd"]);
+        assert_eq!(e.cursor_position(), (0, 2));
+        assert!(e.extra_cursors.is_empty());
+
+        let mut e = editor(&["ab"]);
+        e.set_cursor_position(0, 2);
+        e.extra_cursors = vec![(0, 1), (0, 2)];
+        e.backspace();
+        assert_eq!(contents(&e), vec![""]);
+        assert_eq!(e.cursor_count(), 1);
+    }
+
+    #[test]
+    fn block_selection_splits_tabs() {
+        let mut e = editor(&["a\tbc", "x", "abcdef"]);
+        e.block_anchor = Some((0, 2));
+        e.block_x = 5;
+        e.set_cursor_position(2, 5);
+        assert_eq!(e.copy_selection(), Some("  b\n\ncde".to_owned()));
+
+        e.block_anchor = Some((0, 2));
+        e.set_cursor_position(2, 5);
+        assert_eq!(e.cut_selection(), Some("  b\n\ncde".to_owned()));
+        assert_eq!(contents(&e), vec!["a c", "x", "abf"]);
+        assert_eq!(e.cursor_position(), (2, 2));
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["a\tbc", "x", "abcdef"]);
+    }
+
+    #[test]
+    fn block_paste_pads_short_rows() {
+        let mut e = editor(&["abc", "", "a\tb"]);
+        e.copied_block = Some("12\n34\n56\n78".to_owned());
+        e.set_cursor_position(0, 2);
+        e.paste("12\n34\n56\n78");
+        assert_eq!(contents(&e), vec!["ab12c", "  34", "a 56  b", "  78"]);
+        assert_eq!(e.cursor_position(), (0, 4));
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["abc", "", "a\tb"]);
+        assert!(!e.undo());
+    }
+
+    #[test]
+    fn screen_positions_count_tabs_and_scrolling() {
+        let mut e = editor(&["a\tb", "xyz"]);
+        e.config.soft_wrap = false;
+        assert_eq!(e.position_at_screen(1, 1), (0, 0));
+        assert_eq!(e.position_at_screen(4, 1), (0, 1));
+        assert_eq!(e.position_at_screen(5, 1), (0, 2));
+        assert_eq!(e.position_at_screen(10, 1), (0, 3));
+        assert_eq!(e.position_at_screen(1, 9), (2, 0));
+        e.col_offset = 1;
+        assert_eq!(e.position_at_screen(2, 2), (1, 2));
+    }
+
+    #[test]
+    fn screen_positions_on_wrapped_lines() {
+        let mut e = Editor::new(24, 10);
+        e.rows.push(Row::with_content("abcdefghijklmnopqrstuvw"));
+        e.rows.push(Row::with_content("end"));
+        e.config.soft_wrap = true;
+        e.config.wrap_at_words = false;
+        assert_eq!(e.position_at_screen(10, 1), (0, 9));
+        // The wrap indicator is shown in the first column of continuations
+        assert_eq!(e.position_at_screen(1, 2), (0, 10));
+        assert_eq!(e.position_at_screen(3, 2), (0, 11));
+        assert_eq!(e.position_at_screen(10, 2), (0, 18));
+        assert_eq!(e.position_at_screen(9, 4), (1, 3));
+    }
+
+    #[test]
+    fn wrap_points_split_rows() {
+        assert_eq!(Row::with_content("abcdefghij").wrap_points(4, 1, false, 4), vec![0, 4, 7]);
+        assert_eq!(Row::with_content("ab cd ef").wrap_points(4, 0, true, 4), vec![0, 3, 6]);
+        assert_eq!(Row::with_content("abcdefgh").wrap_points(4, 0, true, 4), vec![0, 4]);
+        assert_eq!(Row::with_content("a\tb").wrap_points(2, 0, false, 4), vec![0, 1, 2]);
+        assert_eq!(Row::with_content("").wrap_points(4, 1, false, 4), vec![0]);
+    }
+
+    #[test]
+    fn scrolls_across_wrapped_lines() {
+        // Two lines of screen, with a row wrapped onto three
+        let mut e = Editor::new(5, 10);
+        e.rows.push(Row::with_content(&"a".repeat(25)));
+        e.rows.push(Row::with_content("end"));
+        e.config.soft_wrap = true;
+        e.config.wrap_at_words = false;
+        e.config.scroll_margin = 0;
+        e.set_cursor_position(1, 0);
+        assert_eq!((e.row_offset, e.wrap_offset), (0, 2));
+        e.set_cursor_position(0, 12);
+        assert_eq!((e.row_offset, e.wrap_offset), (0, 1));
+        e.set_cursor_position(0, 0);
+        assert_eq!((e.row_offset, e.wrap_offset), (0, 0));
+    }
+
+    #[test]
+    fn scrolls_with_no_columns() {
+        let mut e = Editor::new(24, 0);
+        e.rows.push(Row::with_content("a\tbc"));
+        e.config.soft_wrap = false;
+        e.set_cursor_position(0, 3);
+        assert_eq!(e.cursor_position(), (0, 3));
+        e.config.soft_wrap = true;
+        e.set_cursor_position(0, 2);
+        assert_eq!(e.cursor_position(), (0, 2));
+    }
+
+    fn rendered(row: &Row, tab_width: usize, show_whitespace: bool) -> String {
+        row.render(tab_width, show_whitespace).into_iter().map(|(c, _)| c).collect()
+    }
+
+    #[test]
+    fn tabs_fill_to_the_next_stop() {
+        let row = Row::with_content("\ta\tbc\td");
+        assert_eq!(row.render_cols(3), vec![0, 3, 4, 6, 7, 8, 9, 10]);
+        assert_eq!(rendered(&row, 3, false), "   a  bc d");
+        assert_eq!(row.render_col(3, 3), 6);
+        assert_eq!(row.col_at(5, 3), 2);
+        assert_eq!(row.col_at(99, 3), 7);
+        assert_eq!(Row::with_content("a\t").render_cols(1), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn expanded_tabs_reach_the_next_stop() {
+        let mut e = editor(&["a\tb"]);
+        e.config.tab_width = 3;
+        e.config.expand_tabs = true;
+        e.set_cursor_position(0, 2);
+        e.insert_tab();
+        assert_eq!(contents(&e), vec!["a\t   b"]);
+        e.config.expand_tabs = false;
+        e.insert_tab();
+        assert_eq!(contents(&e), vec!["a\t   \tb"]);
+    }
+
+    #[test]
+    fn control_characters_use_caret_notation() {
+        let row = Row::with_content("a\x01b\x7f\u{85}");
+        assert_eq!(row.render_cols(4), vec![0, 1, 3, 4, 6, 7]);
+        assert_eq!(rendered(&row, 4, false), "a^Ab^?\u{fffd}");
+        assert_eq!(row.col_at(2, 4), 1);
+        // Tabs are still expanded from where the caret notation ends
+        assert_eq!(Row::with_content("\x1b\tx").render_cols(4), vec![0, 2, 4, 5]);
+    }
+
+    #[test]
+    fn visible_whitespace() {
+        let row = Row::with_content("\ta b\u{a0} \t ");
+        assert_eq!(rendered(&row, 4, true), "»   a b␣·»  ·");
+        assert_eq!(rendered(&row, 4, false), "    a b\u{a0}     ");
+    }
+
+    #[test]
+    fn finds_matching_brackets() {
+        let e = editor(&["a(b[c]d)e", "{", "  x)", "}"]);
+        assert_eq!(e.find_matching_bracket(0, 1), Some((0, 7)));
+        assert_eq!(e.find_matching_bracket(0, 7), Some((0, 1)));
+        assert_eq!(e.find_matching_bracket(0, 3), Some((0, 5)));
+        assert_eq!(e.find_matching_bracket(1, 0), Some((3, 0)));
+        assert_eq!(e.find_matching_bracket(3, 0), Some((1, 0)));
+        assert_eq!(e.find_matching_bracket(2, 3), None);
+        assert_eq!(e.find_matching_bracket(0, 0), None);
+        assert_eq!(e.find_matching_bracket(9, 0), None);
+    }
+
+    #[test]
+    fn bracket_search_stops_after_a_while() {
+        // The pair spans exactly as many rows as are searched
+        let mut lines = vec!["("];
+        lines.extend(iter::repeat("").take(BRACKET_SEARCH_ROWS - 2));
+        lines.push(")");
+        let e = editor(&lines);
+        assert_eq!(e.find_matching_bracket(0, 0), Some((BRACKET_SEARCH_ROWS - 1, 0)));
+        assert_eq!(e.find_matching_bracket(BRACKET_SEARCH_ROWS - 1, 0), Some((0, 0)));
+
+        lines.insert(1, "");
+        let e = editor(&lines);
+        assert_eq!(e.find_matching_bracket(0, 0), None);
+        assert_eq!(e.find_matching_bracket(BRACKET_SEARCH_ROWS, 0), None);
+    }
+
+    fn numbered(rows: usize) -> Editor {
+        // Ten lines of screen
+        let mut e = Editor::new(13, 80);
+        for i in 0..rows {
+            e.rows.push(Row::with_content(&format!("line {}", i)));
+        }
+        e.config.soft_wrap = false;
+        e
+    }
+
+    #[test]
+    fn keeps_a_scroll_margin() {
+        let mut e = numbered(100);
+        e.config.scroll_margin = 3;
+        e.set_cursor_position(6, 0);
+        assert_eq!(e.row_offset, 0);
+        e.set_cursor_position(7, 0);
+        assert_eq!(e.row_offset, 1);
+        e.set_cursor_position(8, 0);
+        assert_eq!(e.row_offset, 2);
+        e.set_cursor_position(4, 0);
+        assert_eq!(e.row_offset, 1);
+        // The margin gives way at the start of the file
+        e.set_cursor_position(1, 0);
+        assert_eq!(e.row_offset, 0);
+
+        // and is never more than half the screen
+        e.config.scroll_margin = 50;
+        e.set_cursor_position(5, 0);
+        assert_eq!(e.row_offset, 0);
+        e.set_cursor_position(6, 0);
+        assert_eq!(e.row_offset, 1);
+    }
+
+    #[test]
+    fn centers_and_jumps_to_buffer_ends() {
+        let mut e = numbered(100);
+        e.config.scroll_margin = 3;
+        e.set_cursor_position(50, 2);
+        e.center_view();
+        assert_eq!(e.row_offset, 45);
+        assert_eq!(e.cursor_position(), (50, 2));
+
+        e.cursor_to_end_of_buffer();
+        assert_eq!(e.cursor_position(), (99, 7));
+        assert_eq!(e.row_offset, 91);
+        e.cursor_to_start_of_buffer();
+        assert_eq!(e.cursor_position(), (0, 0));
+        assert_eq!(e.row_offset, 0);
+    }
+
+    #[test]
+    fn scrolls_by_line_keeping_the_cursor_row() {
+        let mut e = numbered(100);
+        e.config.scroll_margin = 3;
+        e.set_cursor_position(5, 3);
+        e.scroll_view(CursorDirection::Up);
+        assert_eq!((e.row_offset, e.cursor_position()), (0, (5, 3)));
+        e.scroll_view(CursorDirection::Down);
+        e.scroll_view(CursorDirection::Down);
+        assert_eq!((e.row_offset, e.cursor_position()), (2, (5, 3)));
+        // The cursor is pushed along once it reaches the margin
+        e.scroll_view(CursorDirection::Down);
+        e.scroll_view(CursorDirection::Down);
+        assert_eq!((e.row_offset, e.cursor_position()), (4, (7, 3)));
+        e.scroll_view(CursorDirection::Up);
+        assert_eq!((e.row_offset, e.cursor_position()), (3, (7, 3)));
+    }
+
+    #[test]
+    fn newlines_keep_indentation() {
+        let mut e = editor(&["  foo bar"]);
+        e.config.auto_indent = true;
+        e.config.smart_indent = true;
+        e.set_cursor_position(0, 5);
+        e.newline();
+        assert_eq!(contents(&e), vec!["  foo", "  bar"]);
+        assert_eq!(e.cursor_position(), (1, 2));
+
+        e.config.auto_indent = false;
+        e.set_cursor_position(1, 3);
+        e.newline();
+        assert_eq!(contents(&e), vec!["  foo", "  b", "ar"]);
+        assert_eq!(e.cursor_position(), (2, 0));
+    }
+
+    #[test]
+    fn smart_indent_matches_the_line() {
+        let mut e = editor(&["    if x {", "\tif y {", "if z {"]);
+        e.config.auto_indent = true;
+        e.config.smart_indent = true;
+        e.config.expand_tabs = false;
+        e.set_cursor_position(0, 10);
+        e.newline();
+        assert_eq!(contents(&e)[1], "        ");
+        e.config.expand_tabs = true;
+        e.set_cursor_position(2, 7);
+        e.newline();
+        assert_eq!(contents(&e)[3], "\t\t");
+        // Unindented lines follow the configuration
+        e.set_cursor_position(4, 6);
+        e.newline();
+        assert_eq!(contents(&e)[5], "    ");
+    }
+
+    #[test]
+    fn smart_indent_splits_brackets() {
+        let mut e = editor(&["  f(){}"]);
+        e.config.auto_indent = true;
+        e.config.smart_indent = true;
+        e.config.expand_tabs = true;
+        e.set_cursor_position(0, 6);
+        e.newline();
+        assert_eq!(contents(&e), vec!["  f(){", "      ", "  }"]);
+        assert_eq!(e.cursor_position(), (1, 6));
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["  f(){}"]);
+    }
+
+    #[test]
+    fn shifts_selected_lines() {
+        let mut e = editor(&["a", "", "\tb", "  c", "d"]);
+        e.config.expand_tabs = true;
+        e.select_range((0, 1), (3, 2));
+        e.indent_lines();
+        assert_eq!(contents(&e), vec!["    a", "", "    \tb", "      c", "d"]);
+        assert_eq!(e.selection(), Some(((0, 5), (3, 6))));
+        e.outdent_lines();
+        e.outdent_lines();
+        assert_eq!(contents(&e), vec!["a", "", "b", "c", "d"]);
+        assert_eq!(e.selection(), Some(((0, 0), (3, 0))));
+
+        // A selection ending at the start of a row leaves that row alone
+        e.config.expand_tabs = false;
+        e.select_range((3, 0), (4, 0));
+        e.indent_lines();
+        assert_eq!(contents(&e), vec!["a", "", "b", "\tc", "d"]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["a", "", "b", "c", "d"]);
+    }
+
+    /// Checks that undoing the last edit gives `before`, and redoing it gives
+    /// `after` again.
+    fn check_undo(e: &mut Editor, before: &[&str], after: &[&str]) {
+        assert!(e.undo());
+        assert_eq!(contents(e), before);
+        assert!(e.redo());
+        assert_eq!(contents(e), after);
+    }
+
+    #[test]
+    fn duplicates_lines_and_selections() {
+        let mut e = editor(&["a", "b", "c"]);
+        e.set_cursor_position(1, 1);
+        e.duplicate();
+        assert_eq!(contents(&e), vec!["a", "b", "b", "c"]);
+        assert_eq!(e.cursor_position(), (2, 1));
+        check_undo(&mut e, &["a", "b", "c"], &["a", "b", "b", "c"]);
+        e.set_cursor_position(3, 0);
+        e.duplicate();
+        assert_eq!(contents(&e), vec!["a", "b", "b", "c", "c"]);
+        check_undo(&mut e, &["a", "b", "b", "c"], &["a", "b", "b", "c", "c"]);
+
+        let mut e = editor(&["abc", "d"]);
+        e.select_range((0, 1), (1, 0));
+        e.duplicate();
+        assert_eq!(contents(&e), vec!["abc", "bc", "d"]);
+        assert_eq!(e.selection(), Some(((1, 0), (2, 0))));
+        check_undo(&mut e, &["abc", "d"], &["abc", "bc", "d"]);
+    }
+
+    #[test]
+    fn moves_lines() {
+        let mut e = editor(&["a", "b", "c", "d"]);
+        e.select_range((1, 0), (2, 1));
+        e.move_lines(CursorDirection::Up);
+        assert_eq!(contents(&e), vec!["b", "c", "a", "d"]);
+        assert_eq!(e.selection(), Some(((0, 0), (1, 1))));
+        check_undo(&mut e, &["a", "b", "c", "d"], &["b", "c", "a", "d"]);
+        // Nothing moves past the start of the file
+        e.move_lines(CursorDirection::Up);
+        assert_eq!(contents(&e), vec!["b", "c", "a", "d"]);
+
+        e.move_lines(CursorDirection::Down);
+        e.move_lines(CursorDirection::Down);
+        assert_eq!(contents(&e), vec!["a", "d", "b", "c"]);
+        assert_eq!(e.selection(), Some(((2, 0), (3, 1))));
+        check_undo(&mut e, &["a", "b", "c", "d"], &["a", "d", "b", "c"]);
+        e.move_lines(CursorDirection::Down);
+        assert_eq!(contents(&e), vec!["a", "d", "b", "c"]);
+        assert!(e.undo());
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn joins_lines() {
+        let mut e = editor(&["  a  ", "   b", "c"]);
+        e.join_lines();
+        assert_eq!(contents(&e), vec!["  a b", "c"]);
+        assert_eq!(e.cursor_position(), (0, 4));
+        check_undo(&mut e, &["  a  ", "   b", "c"], &["  a b", "c"]);
+
+        let mut e = editor(&["a", "", "b", "c"]);
+        e.select_range((0, 0), (2, 1));
+        e.join_lines();
+        assert_eq!(contents(&e), vec!["a b", "c"]);
+        check_undo(&mut e, &["a", "", "b", "c"], &["a b", "c"]);
+        // The last row has nothing to join onto it
+        e.set_cursor_position(1, 0);
+        e.join_lines();
+        assert_eq!(contents(&e), vec!["a b", "c"]);
+    }
+
+    #[test]
+    fn deletes_lines() {
+        let mut e = editor(&["a", "bcd", "e"]);
+        e.set_cursor_position(1, 3);
+        e.delete_lines();
+        assert_eq!(contents(&e), vec!["a", "e"]);
+        assert_eq!(e.cursor_position(), (1, 1));
+        check_undo(&mut e, &["a", "bcd", "e"], &["a", "e"]);
+
+        e.select_range((0, 0), (1, 1));
+        e.delete_lines();
+        assert!(contents(&e).is_empty());
+        check_undo(&mut e, &["a", "e"], &[]);
+    }
+
+    #[test]
+    fn sorts_lines() {
+        let mut e = editor(&["c", "a", "b", "a", "z"]);
+        assert_eq!(e.sort_lines(false), None);
+        e.select_range((0, 0), (4, 0));
+        assert_eq!(e.sort_lines(true), Some(1));
+        assert_eq!(contents(&e), vec!["a", "b", "c", "z"]);
+        assert_eq!(e.selection(), Some(((0, 0), (2, 1))));
+        check_undo(&mut e, &["c", "a", "b", "a", "z"], &["a", "b", "c", "z"]);
+        // Sorting rows already in order leaves nothing to undo
+        assert_eq!(e.sort_lines(false), Some(0));
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["c", "a", "b", "a", "z"]);
+    }
+
+    #[test]
+    fn toggles_line_comments() {
+        let mut e = editor(&["fn a() {", "", "    x;", "}"]);
+        assert!(!e.toggle_comment());
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.rs");
+        e.select_range((0, 2), (2, 5));
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), vec!["// fn a() {", "", "//     x;", "}"]);
+        assert_eq!(e.selection(), Some(((0, 5), (2, 8))));
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), vec!["fn a() {", "", "    x;", "}"]);
+        assert_eq!(e.selection(), Some(((0, 2), (2, 5))));
+        check_undo(&mut e, &["// fn a() {", "", "//     x;", "}"], &["fn a() {", "", "    x;", "}"]);
+
+        // Indented rows are commented at their indentation
+        e.clear_selection();
+        e.set_cursor_position(2, 1);
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e)[2], "    // x;");
+        assert_eq!(e.cursor_position(), (2, 1));
+    }
+
+    #[test]
+    fn toggles_block_comments() {
+        let mut e = editor(&["a { }", "", "b { }  "]);
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.css");
+        e.select_range((0, 1), (2, 1));
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), vec!["/* a { }", "", "b { } */"]);
+        assert_eq!(e.selection(), Some(((0, 4), (2, 1))));
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), vec!["a { }", "", "b { }"]);
+        assert_eq!(e.selection(), Some(((0, 1), (2, 1))));
+
+        let mut e = editor(&["  /* a */"]);
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.css");
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), vec!["  a"]);
+        check_undo(&mut e, &["  /* a */"], &["  a"]);
+    }
+
+    #[test]
+    fn block_comments_are_not_nested() {
+        let lines = ["/* a */", "b { }", "/* c */"];
+        let mut e = editor(&lines);
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.css");
+        e.select_range((0, 0), (2, 7));
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), lines);
+        assert!(!e.undo());
+
+        e.select_range((1, 0), (2, 7));
+        assert!(e.toggle_comment());
+        assert_eq!(contents(&e), lines);
+    }
+
+    #[test]
+    fn moves_by_paragraph() {
+        let mut e = editor(&["a", "b", "", "  ", "c", "d"]);
+        e.cursor_to_paragraph(CursorDirection::Up, false);
+        assert_eq!(e.cursor_position(), (0, 0));
+        e.cursor_to_paragraph(CursorDirection::Down, false);
+        assert_eq!(e.cursor_position(), (2, 0));
+        // The last paragraph runs to the end of the buffer
+        e.cursor_to_paragraph(CursorDirection::Down, false);
+        assert_eq!(e.cursor_position(), (5, 1));
+        e.cursor_to_paragraph(CursorDirection::Down, false);
+        assert_eq!(e.cursor_position(), (5, 1));
+        e.cursor_to_paragraph(CursorDirection::Up, false);
+        assert_eq!(e.cursor_position(), (3, 0));
+        e.cursor_to_paragraph(CursorDirection::Up, false);
+        assert_eq!(e.cursor_position(), (0, 0));
+
+        e.cursor_to_paragraph(CursorDirection::Down, true);
+        assert_eq!(e.selection(), Some(((0, 0), (2, 0))));
+    }
+
+    #[test]
+    fn moves_by_function() {
+        let lines = ["use a;", "", "pub fn a() {", "    let fn_b = 1;", "}", "",
+                     "    async fn b() {", "    }", "const fn c() {}"];
+        let mut e = editor(&lines);
+        assert!(!e.cursor_to_function(CursorDirection::Down, false));
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.css");
+        assert!(!e.cursor_to_function(CursorDirection::Down, false));
+
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.rs");
+        assert!(e.cursor_to_function(CursorDirection::Down, false));
+        assert_eq!(e.cursor_position(), (2, 0));
+        assert!(e.cursor_to_function(CursorDirection::Down, false));
+        assert_eq!(e.cursor_position(), (6, 4));
+        assert!(e.cursor_to_function(CursorDirection::Down, false));
+        assert_eq!(e.cursor_position(), (8, 0));
+        assert!(!e.cursor_to_function(CursorDirection::Down, false));
+        assert_eq!(e.cursor_position(), (8, 0));
+        assert!(e.cursor_to_function(CursorDirection::Up, true));
+        assert_eq!(e.selection(), Some(((6, 4), (8, 0))));
+    }
+
+    #[test]
+    fn reflows_list_items() {
+        let lines = ["- one two three four five", "six", "", "1. alpha beta gamma delta"];
+        let mut e = editor(&lines);
+        e.config.fill_column = 20;
+        e.set_cursor_position(1, 1);
+        assert!(e.reflow());
+        let filled = ["- one two three four", "  five six", "", "1. alpha beta gamma delta"];
+        assert_eq!(contents(&e), filled);
+        check_undo(&mut e, &lines, &filled);
+
+        e.set_cursor_position(2, 0);
+        assert!(!e.reflow());
+        e.set_cursor_position(3, 0);
+        assert!(e.reflow());
+        assert_eq!(contents(&e)[3..], ["1. alpha beta gamma", "   delta"]);
+    }
+
+    #[test]
+    fn reflows_comments() {
+        let mut e = editor(&["fn a() {", "    // aaa bbb", "    // ccc ddd eee fff", "}"]);
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.rs");
+        e.config.fill_column = 16;
+        e.set_cursor_position(2, 0);
+        assert!(e.reflow());
+        assert_eq!(contents(&e), vec!["fn a() {", "    // aaa bbb", "    // ccc ddd", "    // eee fff", "}"]);
+
+        // Words longer than the fill column get a line to themselves
+        let mut e = editor(&["// x abcdefghijklmnopqrstuvwxyz y"]);
+        e.syntax_highlight = SyntaxHighlightRule::for_filename("a.rs");
+        e.config.fill_column = 10;
+        assert!(e.reflow());
+        assert_eq!(contents(&e), vec!["// x", "// abcdefghijklmnopqrstuvwxyz", "// y"]);
+    }
+
+    #[test]
+    fn reflows_selected_paragraphs() {
+        let lines = ["a", "b", "", "c", "d", "- e", "f"];
+        let mut e = editor(&lines);
+        e.select_range((0, 0), (6, 1));
+        assert!(e.reflow());
+        assert_eq!(contents(&e), vec!["a b", "", "c d", "- e f"]);
+        assert_eq!(e.selection(), None);
+        // The whole selection is undone in one step
+        assert!(e.undo());
+        assert_eq!(contents(&e), lines);
+    }
+
+    #[test]
+    fn ranks_completions() {
+        let mut e = editor(&["alpha alpine", "alpxyz alp", "alpine alpaca", "alpaca", "alpxyz"]);
+        e.set_cursor_position(1, 3);
+        // Ties in distance go to the more common word, and the word under
+        // the cursor only counts where it appears elsewhere
+        assert_eq!(e.completions("alp"), vec!["alpaca", "alpine", "alpha", "alpxyz"]);
+        assert_eq!(e.completions("alpi"), vec!["alpine"]);
+
+        e.set_cursor_position(1, 10);
+        assert!(e.start_completion());
+        e.accept_completion();
+        assert_eq!(contents(&e)[1], "alpxyz alpxyz");
+        assert!(!e.is_completing());
+    }
+
+    #[test]
+    fn replace_all_keeps_offsets() {
+        let mut e = editor(&["foo bar foo", "foo"]);
+        let search = Search::new("foo", SearchOptions::new()).unwrap();
+        assert_eq!(e.replace_all(&search, "longer\nx", (0, 0)), 3);
+        assert_eq!(contents(&e), vec!["longer", "x bar longer", "x", "longer", "x"]);
+        assert_eq!(e.cursor_position(), (4, 1));
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["foo bar foo", "foo"]);
+        assert!(!e.undo());
+
+        assert_eq!(e.replace_all(&search, "f", (0, 1)), 2);
+        assert_eq!(contents(&e), vec!["foo bar f", "f"]);
+    }
+
+    #[test]
+    fn replace_all_empty_matches() {
+        let mut e = editor(&["abc", ""]);
+        let mut options = SearchOptions::new();
+        options.regex = true;
+        let search = Search::new("x*", options).unwrap();
+        assert_eq!(e.replace_all(&search, "-", (0, 0)), 5);
+        assert_eq!(contents(&e), vec!["-a-b-c-", "-"]);
+        assert!(e.undo());
+        assert_eq!(contents(&e), vec!["abc", ""]);
+
+        let search = Search::new("b*", options).unwrap();
+        assert_eq!(e.replace_all(&search, "-", (0, 0)), 4);
+        assert_eq!(contents(&e), vec!["-a-c-", "-"]);
     }
 }
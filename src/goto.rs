@@ -0,0 +1,127 @@
+use std::cmp;
+use std::path::Path;
+
+fn parse_number(s: &str) -> Result<usize, String> {
+    s.trim().parse().map_err(|_| format!("'{}' is not a number", s.trim()))
+}
+
+/// Works out the position a go-to-line prompt asks for. `spec` can be a line,
+/// `line:column`, a number of lines to move by as `+N` or `-N`, or a
+/// percentage of the way through the file as `N%`. Lines and columns count
+/// from 1, while `current` and the returned position count from 0.
+pub fn parse(spec: &str, current: (usize, usize), lines: usize) -> Result<(usize, usize), String> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Err("No line given".to_owned());
+    }
+    let mut rest = spec.chars();
+    if rest.next_back() == Some('%') {
+        let percent = try!(parse_number(rest.as_str()));
+        let row = cmp::min(lines * cmp::min(percent, 100) / 100, lines.saturating_sub(1));
+        return Ok((row, 0));
+    }
+    let mut rest = spec.chars();
+    match rest.next() {
+        Some('+') => {
+            let n = try!(parse_number(rest.as_str()));
+            return Ok((current.0.saturating_add(n), current.1));
+        },
+        Some('-') => {
+            let n = try!(parse_number(rest.as_str()));
+            return Ok((current.0.saturating_sub(n), current.1));
+        },
+        _ => {}
+    }
+    let mut parts = spec.splitn(2, ':');
+    let line = try!(parse_number(parts.next().unwrap_or("")));
+    let col = match parts.next() {
+        Some(col) => try!(parse_number(col)),
+        None => 1,
+    };
+    Ok((line.saturating_sub(1), col.saturating_sub(1)))
+}
+
+/// Splits a `file:line` or `file:line:column` command line argument into the
+/// file name and the position to open it at, counting from 0. A file that
+/// exists under the full name is opened as it is.
+pub fn split_filename(arg: &str) -> (String, Option<(usize, usize)>) {
+    if Path::new(arg).exists() {
+        return (arg.to_owned(), None);
+    }
+    let mut name = arg;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        let colon = match name.rfind(':') {
+            Some(colon) => colon,
+            None => break,
+        };
+        match name[colon+1..].parse::<usize>() {
+            Ok(n) => numbers.insert(0, n.saturating_sub(1)),
+            Err(_) => break,
+        }
+        name = &name[..colon];
+    }
+    match numbers.len() {
+        0 => (arg.to_owned(), None),
+        1 => (name.to_owned(), Some((numbers[0], 0))),
+        _ => (name.to_owned(), Some((numbers[0], numbers[1]))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use super::{parse, split_filename};
+
+    #[test]
+    fn parses_lines_and_columns() {
+        assert_eq!(parse("12", (0, 0), 100), Ok((11, 0)));
+        assert_eq!(parse(" 12:5 ", (0, 0), 100), Ok((11, 4)));
+        assert_eq!(parse("0", (3, 3), 100), Ok((0, 0)));
+        assert_eq!(parse("0:0", (3, 3), 100), Ok((0, 0)));
+    }
+
+    #[test]
+    fn parses_relative_lines() {
+        assert_eq!(parse("+5", (10, 2), 100), Ok((15, 2)));
+        assert_eq!(parse("-5", (10, 2), 100), Ok((5, 2)));
+        assert_eq!(parse("-50", (10, 2), 100), Ok((0, 2)));
+    }
+
+    #[test]
+    fn parses_percentages() {
+        assert_eq!(parse("50%", (0, 0), 10), Ok((5, 0)));
+        assert_eq!(parse("100%", (0, 0), 10), Ok((9, 0)));
+        assert_eq!(parse("250%", (0, 0), 10), Ok((9, 0)));
+        assert_eq!(parse("0%", (4, 4), 10), Ok((0, 0)));
+        assert_eq!(parse("50%", (0, 0), 0), Ok((0, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(parse("", (0, 0), 10).is_err());
+        assert!(parse("abc", (0, 0), 10).is_err());
+        assert!(parse("3:x", (0, 0), 10).is_err());
+        assert!(parse("+", (0, 0), 10).is_err());
+        assert!(parse("x%", (0, 0), 10).is_err());
+    }
+
+    #[test]
+    fn splits_positions_off_file_names() {
+        assert_eq!(split_filename("no-such-file:42:7"), ("no-such-file".to_owned(), Some((41, 6))));
+        assert_eq!(split_filename("no-such-file:42"), ("no-such-file".to_owned(), Some((41, 0))));
+        assert_eq!(split_filename("no-such-file"), ("no-such-file".to_owned(), None));
+        assert_eq!(split_filename("no:such:file"), ("no:such:file".to_owned(), None));
+        assert_eq!(split_filename("no:such:file:3"), ("no:such:file".to_owned(), Some((2, 0))));
+    }
+
+    #[test]
+    fn keeps_existing_names_with_colons() {
+        let path = env::temp_dir().join("mutxt-goto-test:12");
+        File::create(&path).unwrap();
+        let name = path.to_str().unwrap().to_owned();
+        assert_eq!(split_filename(&name), (name.clone(), None));
+        fs::remove_file(&path).unwrap();
+    }
+}
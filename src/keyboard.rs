@@ -1,5 +1,33 @@
 use std::io;
 use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+use transform::Case;
+
+/// How many times to check for the rest of an escape sequence before
+/// deciding the Escape key was pressed on its own
+const ESCAPE_TIMEOUT_TRIES: usize = 10;
+
+/// How many times to check for more of a bracketed paste before deciding
+/// its end was lost
+const PASTE_TIMEOUT_TRIES: usize = 500;
+
+/// The most bytes of a bracketed paste read before giving up on its end
+const MAX_PASTE_LEN: usize = 16 * 1024 * 1024;
+
+/// Asks the terminal to mark pasted text with `ESC [200~` and `ESC [201~`,
+/// so it isn't taken for typing.
+pub const ENABLE_BRACKETED_PASTE: &'static str = "\x1b[?2004h";
+pub const DISABLE_BRACKETED_PASTE: &'static str = "\x1b[?2004l";
+
+/// Asks the terminal to report mouse presses, drags and the wheel as SGR
+/// `ESC [ <` sequences.
+pub const ENABLE_MOUSE: &'static str = "\x1b[?1002h\x1b[?1006h";
+pub const DISABLE_MOUSE: &'static str = "\x1b[?1002l\x1b[?1006l";
+
+/// What the terminal sends after text pasted in bracketed paste mode
+const PASTE_END: &'static [u8] = b"\x1b[201~";
 
 pub enum Command {
     MoveUp,
@@ -8,6 +36,15 @@ pub enum Command {
     MoveRight,
     MoveLeftWord,
     MoveRightWord,
+    MoveLeftSubword,
+    MoveRightSubword,
+    // The motions below extend the selection when their flag is set
+    PreviousParagraph(bool),
+    NextParagraph(bool),
+    EnclosingBlock(bool),
+    NextBlock(bool),
+    PreviousFunction(bool),
+    NextFunction(bool),
     PageUp,
     PageDown,
     Save,
@@ -23,6 +60,58 @@ pub enum Command {
     BackspaceLine,
     Delete,
     Refresh,
+    ToggleSoftWrap,
+    ToggleExpandTabs,
+    ToggleShowWhitespace,
+    ToggleAutoPair,
+    StripTrailingWhitespace,
+    JumpToMatchingBracket,
+    ToggleSelection,
+    Undo,
+    Redo,
+    Replace,
+    ToggleRegex,
+    ToggleCaseSensitive,
+    ToggleWholeWord,
+    GoToLine,
+    GoToStartOfBuffer,
+    GoToEndOfBuffer,
+    CenterView,
+    Outdent,
+    DuplicateLines,
+    MoveLinesUp,
+    MoveLinesDown,
+    JoinLines,
+    DeleteLines,
+    SortLines,
+    SortLinesUnique,
+    ToggleComment,
+    Reflow,
+    ConvertCase(Case),
+    IncrementNumber(i64),
+    TransposeChars,
+    TransposeWords,
+    Complete,
+    YankPop,
+    StoreRegister,
+    InsertRegister,
+    ScrollUp,
+    ScrollDown,
+    BlockSelectUp,
+    BlockSelectDown,
+    BlockSelectLeft,
+    BlockSelectRight,
+    AddCursorAbove,
+    AddCursorBelow,
+    AddCursorAtNextOccurrence,
+    Escape,
+    /// Text pasted into the terminal, with its line endings made `\n`
+    BracketedPaste(String),
+    /// The left mouse button pressed at a screen column and line
+    MouseClick(usize, usize),
+    /// The mouse dragged with the left button held, or Shift-clicked, to a
+    /// screen column and line
+    MouseDrag(usize, usize),
     Quit,
     Char(char),
     Ignore,
@@ -38,6 +127,149 @@ impl <R: Read> CommandReader<R> {
             input: reader,
         }
     }
+
+    /// Waits for the next byte of a sequence that has started arriving.
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0u8; 1];
+        loop {
+            let nread = self.input.read(&mut byte)
+                .expect("failed to read from stdin");
+            if nread > 0 {
+                return byte[0]
+            }
+        }
+    }
+
+    /// Waits for the next byte of a bracketed paste, checking `tries` times,
+    /// a millisecond apart. Returns `None` if none arrives.
+    fn read_paste_byte(&mut self, tries: usize) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        for _ in 0..tries {
+            let nread = self.input.read(&mut byte)
+                .expect("failed to read from stdin");
+            if nread > 0 {
+                return Some(byte[0])
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    /// Reads the rest of an `ESC [` control sequence, returning the `<`,
+    /// `=`, `>` or `?` marking a private sequence, if there is one, its
+    /// numeric parameters, and the letter or `~` ending it.
+    fn read_control_sequence(&mut self) -> (Option<u8>, Vec<usize>, u8) {
+        let mut param_bytes = vec![];
+        let mut byte = self.read_byte();
+        let marker = match byte {
+            b'<' | b'=' | b'>' | b'?' => {
+                let marker = byte;
+                byte = self.read_byte();
+                Some(marker)
+            },
+            _ => None,
+        };
+        // Parameters and other intermediate bytes come before the final byte
+        while byte < 0x40 {
+            param_bytes.push(byte);
+            byte = self.read_byte();
+        }
+        let params = String::from_utf8_lossy(&param_bytes)
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        (marker, params, byte)
+    }
+
+    /// Reads pasted text up to the end of the bracketed paste, turning
+    /// the carriage returns terminals send for line breaks into newlines.
+    /// If the end doesn't arrive in time, or the paste grows too large, the
+    /// text read so far is returned.
+    fn read_pasted_text(&mut self) -> String {
+        let mut bytes = vec![];
+        while !bytes.ends_with(PASTE_END) {
+            if bytes.len() >= MAX_PASTE_LEN {
+                debug!("giving up on a paste of over {} bytes", MAX_PASTE_LEN);
+                break;
+            }
+            match self.read_paste_byte(PASTE_TIMEOUT_TRIES) {
+                Some(byte) => bytes.push(byte),
+                None => {
+                    debug!("bracketed paste ended without ^[[201~");
+                    break;
+                },
+            }
+        }
+        let len = if bytes.ends_with(PASTE_END) { bytes.len() - PASTE_END.len() } else { bytes.len() };
+        String::from_utf8_lossy(&bytes[..len]).replace("\r\n", "\n").replace('\r', "\n")
+    }
+}
+
+/// Works out the command for an SGR mouse report, `ESC [ < button ; x ; y`
+/// ended by `M` for a press or motion and `m` for a release.
+fn mouse_command(params: &[usize], pressed: bool) -> Command {
+    if params.len() < 3 {
+        return Command::Ignore
+    }
+    let (x, y) = (params[1], params[2]);
+    // The low bits give the button, with 4 added for Shift, 32 for motion
+    // and 64 for the wheel. Alt and Ctrl (8 and 16) make no difference.
+    match (params[0] & !(8 | 16), pressed) {
+        (0, true) => Command::MouseClick(x, y),
+        (4, true) | (32, true) | (36, true) => Command::MouseDrag(x, y),
+        (64, true) => Command::ScrollUp,
+        (65, true) => Command::ScrollDown,
+        _ => Command::Ignore,
+    }
+}
+
+/// Works out the command for an `ESC [` control sequence.
+fn control_sequence_command(params: &[usize], last: u8) -> Command {
+    // Keys pressed with modifiers have them as the second parameter, with 5
+    // meaning Ctrl
+    let modifier = params.get(1).cloned().unwrap_or(1);
+    match (last, modifier) {
+        (b'~', _) => {
+            match params.first().cloned().unwrap_or(0) {
+                3 => Command::Backspace,
+                5 => Command::PageUp,
+                6 => Command::PageDown,
+                1 | 7 => Command::GoHome,
+                4 | 8 => Command::GoEnd,
+                n => {
+                    debug!("ignoring sequence ^[[{}~", n);
+                    Command::Ignore
+                }
+            }
+        },
+        (b'C', 3) => Command::MoveRightSubword,
+        (b'D', 3) => Command::MoveLeftSubword,
+        (b'A', 3) => Command::MoveLinesUp,
+        (b'B', 3) => Command::MoveLinesDown,
+        (b'A', 4) => Command::BlockSelectUp,
+        (b'B', 4) => Command::BlockSelectDown,
+        (b'C', 4) => Command::BlockSelectRight,
+        (b'D', 4) => Command::BlockSelectLeft,
+        (b'A', 7) => Command::AddCursorAbove,
+        (b'B', 7) => Command::AddCursorBelow,
+        (b'A', 5) => Command::ScrollUp,
+        (b'B', 5) => Command::ScrollDown,
+        (b'C', 5) => Command::MoveRightWord,
+        (b'D', 5) => Command::MoveLeftWord,
+        (b'H', 5) => Command::GoToStartOfBuffer,
+        (b'F', 5) => Command::GoToEndOfBuffer,
+        (b'Z', _) => Command::Outdent,
+        (b'A', _) => Command::MoveUp,
+        (b'B', _) => Command::MoveDown,
+        (b'C', _) => Command::MoveRight,
+        (b'D', _) => Command::MoveLeft,
+        (b'H', _) => Command::GoHome,
+        (b'F', _) => Command::GoEnd,
+        (c, _) => {
+            debug!("ignoring sequence ^[[{:?}{}", params, c);
+            Command::Ignore
+        }
+    }
 }
 
 impl <R: Read> Iterator for CommandReader<R> {
@@ -45,133 +277,386 @@ impl <R: Read> Iterator for CommandReader<R> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut single_char = [0u8; 1];
-        let mut seq = [0u8; 3];
+        let mut seq = [0u8; 1];
         let nread = self.input.read(&mut single_char)
             .expect("failed to read from stdin");
         if nread == 0 {
             return Some(Command::Ignore)
         }
 
-        loop {
-            match single_char[0] {
-                0x1B => {
-                    // Escape
-                    loop {
-                        let nread = self.input.read(&mut seq[0..2])
-                            .expect("failed to read from stdin");
-                        if nread > 0 {
-                            break;
-                        }
+        Some(match single_char[0] {
+            0x1B => {
+                // Escape
+                let mut nread = 0;
+                for _ in 0..ESCAPE_TIMEOUT_TRIES {
+                    nread = self.input.read(&mut seq)
+                        .expect("failed to read from stdin");
+                    if nread > 0 {
+                        break;
                     }
-                    match seq[0] {
-                        b'[' => {
-                            // Handle ESC [ sequences
-                            if seq[1] >= b'0' && seq[1] <= b'9' {
-                                // Extended escape, we need one more char
-                                loop {
-                                    let nread = self.input.read(&mut seq[2..3])
-                                        .expect("failed to read from stdin");
-                                    if nread > 0 {
-                                        break;
-                                    }
-                                }
-                                if seq[2] == b'~' {
-                                    match seq[1] {
-                                        b'3' => return Some(Command::Backspace),
-                                        b'5' => return Some(Command::PageUp),
-                                        b'6' => return Some(Command::PageDown),
-                                        c => {
-                                            debug!("ignoring sequence ^[[{}~", c);
-                                            return Some(Command::Ignore)
-                                        }
-                                    }
-                                }
-                            } else {
-                                match seq[1] {
-                                    b'A' => return Some(Command::MoveUp),
-                                    b'B' => return Some(Command::MoveDown),
-                                    b'C' => return Some(Command::MoveRight),
-                                    b'D' => return Some(Command::MoveLeft),
-                                    b'H' => return Some(Command::GoHome),
-                                    b'F' => return Some(Command::GoEnd),
-                                    c => {
-                                        debug!("ignoring sequence ^[[{}", c);
-                                        return Some(Command::Ignore)
-                                    }
-                                }
-                            }
-                        },
-                        b'O' => {
-                            // Handle ESC O sequences
-                            match seq[1] {
-                                b'H' => return Some(Command::GoHome),
-                                b'F' => return Some(Command::GoEnd),
-                                c => {
-                                    debug!("ignoring sequence ^[O{}", c);
-                                    return Some(Command::Ignore)
-                                }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                if nread == 0 {
+                    return Some(Command::Escape)
+                }
+                match seq[0] {
+                    b'[' => {
+                        // Handle ESC [ sequences
+                        let (marker, params, last) = self.read_control_sequence();
+                        match (marker, last) {
+                            (Some(b'<'), b'M') => mouse_command(&params, true),
+                            (Some(b'<'), b'm') => mouse_command(&params, false),
+                            (Some(_), _) => {
+                                debug!("ignoring private sequence ^[[{:?}{}", params, last);
+                                Command::Ignore
+                            },
+                            (None, b'~') if params == [200] => {
+                                Command::BracketedPaste(self.read_pasted_text())
+                            },
+                            (None, _) => control_sequence_command(&params, last),
+                        }
+                    },
+                    b'O' => {
+                        // Handle ESC O sequences
+                        match self.read_byte() {
+                            b'H' => Command::GoHome,
+                            b'F' => Command::GoEnd,
+                            c => {
+                                debug!("ignoring sequence ^[O{}", c);
+                                Command::Ignore
                             }
-                        },
-                        b'5' => {
-                            // Handle ESC 5 sequences
-                            match seq[1] {
-                                b'D' => return Some(Command::MoveLeftWord),
-                                b'C' => return Some(Command::MoveRightWord),
-                                c => {
-                                    debug!("ignoring sequence ^[5{}", c);
-                                    return Some(Command::Ignore)
-                                }
+                        }
+                    },
+                    b'5' => {
+                        // Handle ESC 5 sequences
+                        match self.read_byte() {
+                            b'D' => Command::MoveLeftWord,
+                            b'C' => Command::MoveRightWord,
+                            c => {
+                                debug!("ignoring sequence ^[5{}", c);
+                                Command::Ignore
                             }
-                        },
-                        c => {
-                            debug!("ignoring sequence starting with ^[{}", c);
-                            return Some(Command::Ignore)
                         }
+                    },
+                    b'<' => {
+                        // Alt-<
+                        Command::GoToStartOfBuffer
+                    },
+                    b'>' => {
+                        // Alt->
+                        Command::GoToEndOfBuffer
+                    },
+                    b'.' => {
+                        // Alt-.
+                        Command::CenterView
+                    },
+                    b'z' => {
+                        // Alt-z
+                        Command::ToggleSoftWrap
+                    },
+                    b'i' => {
+                        // Alt-i
+                        Command::ToggleExpandTabs
+                    },
+                    b'v' => {
+                        // Alt-v
+                        Command::ToggleShowWhitespace
+                    },
+                    b'w' => {
+                        // Alt-w
+                        Command::StripTrailingWhitespace
+                    },
+                    b'd' => {
+                        // Alt-d
+                        Command::DuplicateLines
+                    },
+                    b'j' => {
+                        // Alt-j
+                        Command::JoinLines
+                    },
+                    b's' => {
+                        // Alt-s
+                        Command::SortLines
+                    },
+                    b'S' => {
+                        // Alt-Shift-s
+                        Command::SortLinesUnique
+                    },
+                    b';' => {
+                        // Alt-;
+                        Command::ToggleComment
+                    },
+                    b'q' => {
+                        // Alt-q
+                        Command::Reflow
+                    },
+                    b'u' => {
+                        // Alt-u
+                        Command::ConvertCase(Case::Upper)
+                    },
+                    b'l' => {
+                        // Alt-l
+                        Command::ConvertCase(Case::Lower)
+                    },
+                    b'T' => {
+                        // Alt-Shift-t
+                        Command::ConvertCase(Case::Title)
+                    },
+                    b'_' => {
+                        // Alt-_
+                        Command::ConvertCase(Case::Snake)
+                    },
+                    b'C' => {
+                        // Alt-Shift-c
+                        Command::ConvertCase(Case::Camel)
+                    },
+                    b'K' => {
+                        // Alt-Shift-k
+                        Command::ConvertCase(Case::Kebab)
+                    },
+                    b'=' => {
+                        // Alt-=
+                        Command::IncrementNumber(1)
+                    },
+                    b'-' => {
+                        // Alt--
+                        Command::IncrementNumber(-1)
+                    },
+                    b't' => {
+                        // Alt-t
+                        Command::TransposeWords
+                    },
+                    b'y' => {
+                        // Alt-y
+                        Command::YankPop
+                    },
+                    b'x' => {
+                        // Alt-x
+                        Command::StoreRegister
+                    },
+                    b'"' => {
+                        // Alt-"
+                        Command::InsertRegister
+                    },
+                    b'p' => {
+                        // Alt-p
+                        Command::ToggleAutoPair
+                    },
+                    b'm' => {
+                        // Alt-m
+                        Command::JumpToMatchingBracket
+                    },
+                    b'r' => {
+                        // Alt-r
+                        Command::ToggleRegex
+                    },
+                    b'c' => {
+                        // Alt-c
+                        Command::ToggleCaseSensitive
+                    },
+                    b'b' => {
+                        // Alt-b
+                        Command::ToggleWholeWord
+                    },
+                    b'a' | b'A' => {
+                        // Alt-a, or Alt-Shift-a to extend the selection
+                        Command::PreviousParagraph(seq[0] == b'A')
+                    },
+                    b'e' | b'E' => {
+                        // Alt-e, or Alt-Shift-e to extend the selection
+                        Command::NextParagraph(seq[0] == b'E')
+                    },
+                    b'h' | b'H' => {
+                        // Alt-h, or Alt-Shift-h to extend the selection
+                        Command::EnclosingBlock(seq[0] == b'H')
+                    },
+                    b'n' | b'N' => {
+                        // Alt-n, or Alt-Shift-n to extend the selection
+                        Command::NextBlock(seq[0] == b'N')
+                    },
+                    b'g' | b'G' => {
+                        // Alt-g, or Alt-Shift-g to extend the selection
+                        Command::PreviousFunction(seq[0] == b'G')
+                    },
+                    b'f' | b'F' => {
+                        // Alt-f, or Alt-Shift-f to extend the selection
+                        Command::NextFunction(seq[0] == b'F')
+                    },
+                    c => {
+                        debug!("ignoring sequence starting with ^[{}", c);
+                        Command::Ignore
                     }
-                },
-                0x7F => {
-                    return Some(Command::Backspace)
-                },
-                0x03 => {
-                    return Some(Command::Copy)
-                },
-                0x16 => {
-                    return Some(Command::Paste)
-                },
-                0x18 => {
-                    return Some(Command::Cut)
-                },
-                0x11 => {
-                    return Some(Command::Quit)
-                },
-                0x0C => {
-                    return Some(Command::Refresh)
-                },
-                0x13 => {
-                    return Some(Command::Save)
-                },
-                0x0F => {
-                    return Some(Command::Open)
-                },
-                0x0D => {
-                    return Some(Command::Char('\n'))
-                },
-                0x17 | 0x08 => {
-                    return Some(Command::BackspaceWord)
-                },
-                0x15 => {
-                    return Some(Command::BackspaceLine)
-                },
-                0x01 => {
-                    return Some(Command::GoHome)
-                },
-                0x05 => {
-                    return Some(Command::GoEnd)
-                },
-                ch => {
-                    return Some(Command::Char(ch as char))
                 }
-            }
+            },
+            0x7F => Command::Backspace,
+            0x00 => {
+                // Ctrl-Space
+                Command::ToggleSelection
+            },
+            0x1A => Command::Undo,
+            0x19 => Command::Redo,
+            0x12 => Command::Replace,
+            0x06 => Command::Find,
+            0x07 => Command::GoToLine,
+            0x0B => Command::DeleteLines,
+            0x14 => Command::TransposeChars,
+            0x04 => Command::AddCursorAtNextOccurrence,
+            0x0E => Command::Complete,
+            0x1F => {
+                // Ctrl-/
+                Command::ToggleComment
+            },
+            0x03 => Command::Copy,
+            0x16 => Command::Paste,
+            0x18 => Command::Cut,
+            0x11 => Command::Quit,
+            0x0C => Command::Refresh,
+            0x13 => Command::Save,
+            0x0F => Command::Open,
+            0x0D => Command::Char('\n'),
+            0x17 | 0x08 => Command::BackspaceWord,
+            0x15 => Command::BackspaceLine,
+            0x01 => Command::GoHome,
+            0x05 => Command::GoEnd,
+            ch => Command::Char(ch as char),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, CommandReader};
+
+    #[test]
+    fn reads_bracketed_paste() {
+        let mut reader = CommandReader::commands(&b"\x1b[200~a\r\nb\rc\x1b[201~"[..]);
+        match reader.next() {
+            Some(Command::BracketedPaste(ref text)) if text == "a\nb\nc" => {},
+            _ => panic!("expected the pasted text"),
+        }
+    }
+
+    #[test]
+    fn reads_keys_after_paste() {
+        let mut reader = CommandReader::commands(&b"\x1b[200~\x1b[A\x1b[201~x\x1b[A"[..]);
+        match reader.next() {
+            Some(Command::BracketedPaste(ref text)) if text == "\x1b[A" => {},
+            _ => panic!("expected the pasted text"),
+        }
+        match reader.next() {
+            Some(Command::Char('x')) => {},
+            _ => panic!("expected x"),
+        }
+        match reader.next() {
+            Some(Command::MoveUp) => {},
+            _ => panic!("expected up"),
+        }
+    }
+
+    #[test]
+    fn gives_up_on_unfinished_paste() {
+        let mut reader = CommandReader::commands(&b"\x1b[200~a\rb\x1b[20"[..]);
+        match reader.next() {
+            Some(Command::BracketedPaste(ref text)) if text == "a\nb\x1b[20" => {},
+            _ => panic!("expected the text pasted so far"),
+        }
+    }
+
+    fn mouse(report: &[u8]) -> Command {
+        CommandReader::commands(report).next().unwrap()
+    }
+
+    #[test]
+    fn reads_mouse_reports() {
+        match mouse(b"\x1b[<0;12;5M") {
+            Command::MouseClick(12, 5) => {},
+            _ => panic!("expected a click"),
+        }
+        match mouse(b"\x1b[<0;12;5m") {
+            Command::Ignore => {},
+            _ => panic!("expected a release to be ignored"),
+        }
+        match mouse(b"\x1b[<32;13;6M") {
+            Command::MouseDrag(13, 6) => {},
+            _ => panic!("expected a drag"),
+        }
+        match mouse(b"\x1b[<4;3;4M") {
+            Command::MouseDrag(3, 4) => {},
+            _ => panic!("expected a Shift-click to drag"),
+        }
+        match mouse(b"\x1b[<64;1;1M") {
+            Command::ScrollUp => {},
+            _ => panic!("expected the wheel up"),
+        }
+        match mouse(b"\x1b[<65;1;1M") {
+            Command::ScrollDown => {},
+            _ => panic!("expected the wheel down"),
+        }
+        match mouse(b"\x1b[<2;1;1M") {
+            Command::Ignore => {},
+            _ => panic!("expected the right button to be ignored"),
+        }
+    }
+
+    #[test]
+    fn mouse_reports_ignore_alt_and_ctrl() {
+        match mouse(b"\x1b[<24;7;8M") {
+            Command::MouseClick(7, 8) => {},
+            _ => panic!("expected a click"),
+        }
+        match mouse(b"\x1b[<20;7;8M") {
+            Command::MouseDrag(7, 8) => {},
+            _ => panic!("expected a Shift-click to drag"),
+        }
+        match mouse(b"\x1b[<80;1;1M") {
+            Command::ScrollUp => {},
+            _ => panic!("expected the wheel up"),
+        }
+    }
+
+    #[test]
+    fn reads_keys_with_modifiers() {
+        let mut reader = CommandReader::commands(&b"\x1b[1;5H\x1b[1;5F\x1b[1;5A\x1b[1;5B\x1b[H\x1b[4~"[..]);
+        match reader.next() {
+            Some(Command::GoToStartOfBuffer) => {},
+            _ => panic!("expected Ctrl-Home"),
+        }
+        match reader.next() {
+            Some(Command::GoToEndOfBuffer) => {},
+            _ => panic!("expected Ctrl-End"),
+        }
+        match reader.next() {
+            Some(Command::ScrollUp) => {},
+            _ => panic!("expected Ctrl-Up"),
+        }
+        match reader.next() {
+            Some(Command::ScrollDown) => {},
+            _ => panic!("expected Ctrl-Down"),
+        }
+        match reader.next() {
+            Some(Command::GoHome) => {},
+            _ => panic!("expected Home"),
+        }
+        match reader.next() {
+            Some(Command::GoEnd) => {},
+            _ => panic!("expected End"),
+        }
+    }
+
+    #[test]
+    fn reads_alt_keys() {
+        let mut reader = CommandReader::commands(&b"\x1b<\x1b>\x1b."[..]);
+        match reader.next() {
+            Some(Command::GoToStartOfBuffer) => {},
+            _ => panic!("expected Alt-<"),
+        }
+        match reader.next() {
+            Some(Command::GoToEndOfBuffer) => {},
+            _ => panic!("expected Alt->"),
+        }
+        match reader.next() {
+            Some(Command::CenterView) => {},
+            _ => panic!("expected Alt-."),
         }
     }
 }
@@ -6,10 +6,18 @@ extern crate bitflags;
 extern crate log;
 extern crate env_logger;
 extern crate clipboard;
+extern crate regex;
 
+mod config;
 mod editor;
 mod clip;
+mod goto;
 mod keyboard;
+mod search;
+mod status;
+mod syntax;
+mod transform;
+mod words;
 
 use std::env;
 use std::io;
@@ -26,12 +34,16 @@ use termion::{terminal_size, async_stdin};
 use nix::sys::signal;
 
 const HELP_MSG: &'static str = "HELP: CTRL-S to save the file, and CTRL-Q to quit.";
+/// The most time between two clicks in the same place for them to be a
+/// double-click, in milliseconds
+const DOUBLE_CLICK_TIME: u64 = 400;
+const REPLACE_QUESTION: &'static str = "Replace this occurrence? (y)es, (n)o, (a)ll, (q)uit";
 #[allow(non_upper_case_globals)]
 static ShouldResizeWindow: AtomicBool = ATOMIC_BOOL_INIT;
 
 macro_rules! render {
-    ($editor:ident, $stdout:ident) => {
-        $editor.render(&mut $stdout).expect("Failed to render");
+    ($editor:expr, $stdout:expr) => {
+        $editor.render($stdout).expect("Failed to render");
         $stdout.flush().unwrap();
     }
 }
@@ -42,22 +54,261 @@ extern fn signal_handler(signo: i32) {
     }
 }
 
+/// Waits for the next command from the keyboard.
+fn next_command<R: Read>(stdin: &mut keyboard::CommandReader<R>) -> keyboard::Command {
+    loop {
+        match stdin.next() {
+            Some(keyboard::Command::Ignore) | None => thread::sleep(Duration::from_millis(10)),
+            Some(command) => return command,
+        }
+    }
+}
+
+/// Handles a key pressed while the completion popup is open, returning the
+/// command to carry on with if the popup doesn't use it.
+fn completion_command(editor: &mut editor::Editor,
+                      command: keyboard::Command) -> Option<keyboard::Command> {
+    use keyboard::Command::*;
+    match command {
+        MoveUp => editor.select_completion(editor::CursorDirection::Up),
+        MoveDown => editor.select_completion(editor::CursorDirection::Down),
+        Char('\n') | Char('\t') => editor.accept_completion(),
+        Escape => editor.cancel_completion(),
+        Ignore => {},
+        // Typing narrows down the completions
+        Char(_) | Backspace => return Some(command),
+        _ => {
+            editor.cancel_completion();
+            return Some(command);
+        },
+    }
+    None
+}
+
+/// Asks the user to type a line of text in the status message area,
+/// returning `None` if they cancel with Escape.
+fn prompt<R: Read, W: Write>(editor: &mut editor::Editor,
+                             stdin: &mut keyboard::CommandReader<R>,
+                             stdout: &mut W,
+                             msg: &str) -> Option<String> {
+    use keyboard::Command::*;
+    let mut input = String::new();
+    loop {
+        editor.display_status(format!("{}{}", msg, input));
+        render!(editor, stdout);
+        match next_command(stdin) {
+            Char('\n') => break,
+            Escape => {
+                editor.empty_status();
+                return None;
+            },
+            Backspace => {
+                input.pop();
+            },
+            Char(c) if !c.is_control() || c == '\t' => input.push(c),
+            BracketedPaste(text) => input.push_str(text.lines().next().unwrap_or("")),
+            _ => {}
+        }
+    }
+    editor.empty_status();
+    Some(input)
+}
+
+/// A match found by a search, as its start and end positions
+type Match = ((usize, usize), (usize, usize));
+
+/// Asks for a search pattern, selecting the first match after the cursor as
+/// it is typed. Up and Down step through the matches, and Alt-r, Alt-c and
+/// Alt-b toggle regex, case sensitive and whole word matching. Returns the
+/// search and the match selected when Enter was pressed, or the message to
+/// display if the search was cancelled or the pattern is invalid.
+fn prompt_search<R: Read, W: Write>(editor: &mut editor::Editor,
+                                    stdin: &mut keyboard::CommandReader<R>,
+                                    stdout: &mut W,
+                                    label: &str,
+                                    options: &mut search::SearchOptions)
+                                    -> Result<(search::Search, Option<Match>), String> {
+    use keyboard::Command::*;
+    let origin = editor.cursor_position();
+    let mut input = String::new();
+    let mut current: Option<Match> = None;
+    loop {
+        let compiled = search::Search::new(&input, *options);
+        let error = match compiled {
+            Err(ref e) if !input.is_empty() => format!(" [{}]", e),
+            _ => String::new(),
+        };
+        editor.display_status(format!("{} ({}): {}{}", label, options.describe(), input, error));
+        render!(editor, stdout);
+
+        match next_command(stdin) {
+            Char('\n') => break,
+            Escape => {
+                editor.clear_selection();
+                editor.set_cursor_position(origin.0, origin.1);
+                editor.empty_status();
+                return Err(format!("{} cancelled", label));
+            },
+            MoveDown => {
+                if let (Ok(ref search), Some((start, _))) = (compiled, current) {
+                    current = editor.position_after(start)
+                        .and_then(|from| editor.find_next(search, from))
+                        .or_else(|| editor.find_next(search, (0, 0)));
+                }
+            },
+            MoveUp => {
+                if let (Ok(ref search), Some((start, _))) = (compiled, current) {
+                    let end = editor.end_position();
+                    current = editor.find_prev(search, start)
+                        .or_else(|| editor.find_prev(search, end));
+                }
+            },
+            command => {
+                match command {
+                    Backspace => {
+                        input.pop();
+                    },
+                    ToggleRegex => options.regex = !options.regex,
+                    ToggleCaseSensitive => options.case_sensitive = !options.case_sensitive,
+                    ToggleWholeWord => options.whole_word = !options.whole_word,
+                    Char(c) if !c.is_control() || c == '\t' => input.push(c),
+                    BracketedPaste(ref text) => input.push_str(text.lines().next().unwrap_or("")),
+                    _ => continue,
+                }
+                // Look again from where the search started, wrapping around
+                current = match search::Search::new(&input, *options) {
+                    Ok(ref search) if !input.is_empty() => {
+                        editor.find_next(search, origin).or_else(|| editor.find_next(search, (0, 0)))
+                    },
+                    _ => None,
+                };
+            }
+        }
+        match current {
+            Some((start, end)) => editor.select_range(start, end),
+            None => {
+                editor.clear_selection();
+                editor.set_cursor_position(origin.0, origin.1);
+            }
+        }
+    }
+    editor.empty_status();
+    if input.is_empty() {
+        editor.clear_selection();
+        return Err("Nothing to search for".to_owned());
+    }
+    search::Search::new(&input, *options)
+        .map(|search| (search, current))
+        .map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+/// Asks for a pattern and its replacement, then steps through the matches
+/// asking whether to replace each one. Returns the message to display.
+fn find_and_replace<R: Read, W: Write>(editor: &mut editor::Editor,
+                                       stdin: &mut keyboard::CommandReader<R>,
+                                       stdout: &mut W,
+                                       options: &mut search::SearchOptions) -> String {
+    use keyboard::Command::*;
+    let original_position = editor.cursor_position();
+    let search = match prompt_search(editor, stdin, stdout, "Replace", options) {
+        Ok((search, _)) => search,
+        Err(msg) => return msg,
+    };
+    let msg = format!("Replace '{}' with: ", search.pattern());
+    let replacement = match prompt(editor, stdin, stdout, &msg) {
+        Some(r) => r,
+        None => {
+            editor.clear_selection();
+            editor.set_cursor_position(original_position.0, original_position.1);
+            return "Replace cancelled".to_owned();
+        }
+    };
+
+    let mut pos = (0, 0);
+    let mut last_end = None;
+    let mut count = 0;
+    editor.begin_undo_group();
+    while let Some((start, end)) = editor.find_next(&search, pos) {
+        // An empty match right after the previous one isn't a new match
+        if start == end && last_end == Some(start) {
+            match editor.position_after(start) {
+                Some(next) => {
+                    pos = next;
+                    continue;
+                },
+                None => break,
+            }
+        }
+        editor.select_range(start, end);
+        editor.display_status(REPLACE_QUESTION);
+        render!(editor, stdout);
+        match next_command(stdin) {
+            Char('y') => {
+                pos = editor.replace_match(&search, start, end, &replacement);
+                last_end = Some(pos);
+                count += 1;
+            },
+            Char('n') => {
+                pos = end;
+                last_end = Some(end);
+            },
+            Char('a') => {
+                count += editor.replace_all(&search, &replacement, start);
+                break;
+            },
+            Char('q') | Escape => break,
+            _ => {
+                pos = start;
+            }
+        }
+    }
+    editor.clear_selection();
+    editor.end_undo_group();
+    if count == 0 {
+        editor.set_cursor_position(original_position.0, original_position.1);
+    }
+    format!("Replaced {} occurrence{}", count, if count == 1 { "" } else { "s" })
+}
+
 fn main() {
     env_logger::init().expect("failed to initialize logging");
 
     let status_gap = Duration::from_secs(10);
-    let filename = env::args().nth(1)
-        .expect("Usage: mutxt <filename>");
+    let mut filename = None;
+    let mut position = None;
+    for arg in env::args().skip(1) {
+        let mut rest = arg.chars();
+        if rest.next() == Some('+') {
+            // `+42` or `+42:7` opens the file at that line and column
+            position = goto::parse(rest.as_str(), (0, 0), 0).ok();
+        } else {
+            let (name, pos) = goto::split_filename(&arg);
+            filename = Some(name);
+            position = position.or(pos);
+        }
+    }
+    let filename = filename.expect("Usage: mutxt <filename>[:line[:column]] [+line[:column]]");
     let (screen_cols, screen_rows) = terminal_size()
         .expect("Could not get the terminal size");
     let mut stdin = keyboard::CommandReader::commands(async_stdin());
     let mut stdout = io::stdout().into_raw_mode()
         .expect("Could not put stdout into raw mode");
     let mut editor = editor::Editor::new(screen_rows as usize, screen_cols as usize);
-    let mut clipbrd = clip::Clipboard::new();
+    let config = config::Config::load();
+    let mouse = config.mouse;
+    let mut clipbrd = clip::Clipboard::new(&config);
+    editor.set_config(config);
+    write!(stdout, "{}", keyboard::ENABLE_BRACKETED_PASTE).unwrap();
+    if mouse {
+        write!(stdout, "{}", keyboard::ENABLE_MOUSE).unwrap();
+    }
+    let mut search_options = search::SearchOptions::new();
     editor.display_status(HELP_MSG);
     editor.open_file(&filename).expect("Could not open the file provided");
-    render!(editor, stdout);
+    if let Some((row, col)) = position {
+        editor.jump_to(row, col);
+    }
+    render!(editor, &mut stdout);
 
     unsafe {
         signal::sigaction(signal::SIGWINCH,
@@ -69,9 +320,29 @@ fn main() {
     }
 
     let mut last_time_of_status = Instant::now();
+    // Whether the last command killed text or pasted, so that a kill can be
+    // joined onto the one before and yank-pop can follow a paste
+    let mut last_kill = false;
+    let mut last_yank = false;
+    // When and where the mouse was last clicked, to spot double-clicks
+    let mut last_click: Option<(Instant, (usize, usize))> = None;
     loop {
         if let Some(command) = stdin.next() {
             use keyboard::Command::*;
+            let completing = editor.is_completing();
+            let command = if completing {
+                completion_command(&mut editor, command).unwrap_or(Ignore)
+            } else {
+                command
+            };
+            let (kill_continues, yank_continues) = (last_kill, last_yank);
+            match command {
+                Ignore => {},
+                _ => {
+                    last_kill = false;
+                    last_yank = false;
+                },
+            }
             match command {
                 MoveUp => {
                     editor.move_cursor(editor::CursorDirection::Up);
@@ -91,6 +362,42 @@ fn main() {
                 MoveRightWord => {
                     editor.cursor_to_right_word();
                 },
+                MoveLeftSubword => {
+                    editor.cursor_to_left_subword();
+                },
+                MoveRightSubword => {
+                    editor.cursor_to_right_subword();
+                },
+                PreviousParagraph(extend) => {
+                    editor.cursor_to_paragraph(editor::CursorDirection::Up, extend);
+                },
+                NextParagraph(extend) => {
+                    editor.cursor_to_paragraph(editor::CursorDirection::Down, extend);
+                },
+                EnclosingBlock(extend) => {
+                    if !editor.cursor_to_enclosing_block(extend) {
+                        editor.display_status("No enclosing block");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                NextBlock(extend) => {
+                    if !editor.cursor_to_next_block(extend) {
+                        editor.display_status("No more blocks");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                PreviousFunction(extend) => {
+                    if !editor.cursor_to_function(editor::CursorDirection::Up, extend) {
+                        editor.display_status("No previous function");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                NextFunction(extend) => {
+                    if !editor.cursor_to_function(editor::CursorDirection::Down, extend) {
+                        editor.display_status("No next function");
+                        last_time_of_status = Instant::now();
+                    }
+                },
                 PageUp => {
                     editor.page_cursor(editor::CursorDirection::Up);
                 },
@@ -110,16 +417,106 @@ fn main() {
                     unimplemented!()
                 },
                 Find => {
-                    unimplemented!()
+                    match prompt_search(&mut editor, &mut stdin, &mut stdout, "Search", &mut search_options) {
+                        Ok((_, Some((start, _)))) => {
+                            editor.clear_selection();
+                            editor.set_cursor_position(start.0, start.1);
+                        },
+                        Ok((search, None)) => {
+                            editor.display_status(format!("No match for '{}'", search.pattern()));
+                            last_time_of_status = Instant::now();
+                        },
+                        Err(msg) => {
+                            editor.display_status(msg);
+                            last_time_of_status = Instant::now();
+                        }
+                    }
                 },
                 Cut => {
-                    unimplemented!()
+                    match editor.cut_selection() {
+                        Some(text) => {
+                            clipbrd.kill(text, false, kill_continues);
+                            last_kill = true;
+                        },
+                        None => {
+                            editor.display_status("Nothing selected");
+                            last_time_of_status = Instant::now();
+                        }
+                    }
                 },
                 Copy => {
-                    unimplemented!()
+                    match editor.copy_selection() {
+                        Some(text) => clipbrd.set(text),
+                        None => {
+                            editor.display_status("Nothing selected");
+                            last_time_of_status = Instant::now();
+                        }
+                    }
                 },
                 Paste => {
-                    editor.insert_str(clipbrd.get());
+                    editor.paste(&clipbrd.get());
+                    last_yank = true;
+                },
+                MouseClick(x, y) => {
+                    let double_click = match last_click {
+                        Some((time, pos)) => {
+                            pos == (x, y) && time.elapsed() < Duration::from_millis(DOUBLE_CLICK_TIME)
+                        },
+                        None => false,
+                    };
+                    if double_click && editor.select_word_at(x, y) {
+                        last_click = None;
+                    } else {
+                        editor.click(x, y);
+                        last_click = Some((Instant::now(), (x, y)));
+                    }
+                },
+                MouseDrag(x, y) => {
+                    editor.drag_to(x, y);
+                },
+                BracketedPaste(text) => {
+                    // Inserted as it is, without the indenting and bracket
+                    // pairing typing it would trigger
+                    editor.insert_str(&text);
+                },
+                YankPop => {
+                    let popped = if yank_continues { clipbrd.yank_pop() } else { None };
+                    match popped {
+                        Some(ref text) if editor.replace_paste(text) => last_yank = true,
+                        _ => {
+                            editor.display_status("Paste first to go back through older kills");
+                            last_time_of_status = Instant::now();
+                        },
+                    }
+                },
+                StoreRegister => {
+                    match editor.copy_selection() {
+                        Some(text) => {
+                            let msg = "Store selection in register: ";
+                            if let Some(name) = prompt(&mut editor, &mut stdin, &mut stdout, msg) {
+                                if !name.is_empty() {
+                                    clipbrd.set_register(&name, text);
+                                }
+                            }
+                        },
+                        None => {
+                            editor.display_status("Nothing selected");
+                            last_time_of_status = Instant::now();
+                        }
+                    }
+                },
+                InsertRegister => {
+                    let msg = "Insert register: ";
+                    if let Some(name) = prompt(&mut editor, &mut stdin, &mut stdout, msg) {
+                        let text = clipbrd.register(&name).map(|text| text.to_owned());
+                        match text {
+                            Some(text) => editor.paste(&text),
+                            None => {
+                                editor.display_status(format!("No register '{}'", name));
+                                last_time_of_status = Instant::now();
+                            }
+                        }
+                    }
                 },
                 GoHome => {
                     editor.cursor_to_start_of_line();
@@ -128,10 +525,12 @@ fn main() {
                     editor.cursor_to_end_of_line();
                 },
                 BackspaceWord => {
-                    editor.backspace_word();
+                    clipbrd.kill(editor.backspace_word(), true, kill_continues);
+                    last_kill = true;
                 },
                 BackspaceLine => {
-                    editor.backspace_to_start_of_line();
+                    clipbrd.kill(editor.backspace_to_start_of_line(), true, kill_continues);
+                    last_kill = true;
                 },
                 Backspace => {
                     editor.backspace();
@@ -141,15 +540,254 @@ fn main() {
                         .expect("Could not get the terminal size");
                     editor.set_screen_size(screen_rows as usize, screen_cols as usize);
                 },
+                ToggleSoftWrap => {
+                    let status_msg = if editor.toggle_soft_wrap() {
+                        "Soft wrap enabled"
+                    } else {
+                        "Soft wrap disabled"
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                ToggleExpandTabs => {
+                    let status_msg = if editor.toggle_expand_tabs() {
+                        "Tab inserts spaces"
+                    } else {
+                        "Tab inserts tabs"
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                ToggleShowWhitespace => {
+                    let status_msg = if editor.toggle_show_whitespace() {
+                        "Showing whitespace"
+                    } else {
+                        "Hiding whitespace"
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                ToggleAutoPair => {
+                    let status_msg = if editor.toggle_auto_pair() {
+                        "Auto-pairing enabled"
+                    } else {
+                        "Auto-pairing disabled"
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                StripTrailingWhitespace => {
+                    let stripped = editor.strip_trailing_whitespace();
+                    editor.display_status(format!("Stripped trailing whitespace from {} lines", stripped));
+                    last_time_of_status = Instant::now();
+                },
+                JumpToMatchingBracket => {
+                    if !editor.jump_to_matching_bracket() {
+                        editor.display_status("No matching bracket");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                ToggleSelection => {
+                    let status_msg = if editor.toggle_selection() {
+                        "Selection started"
+                    } else {
+                        "Selection cleared"
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                Undo => {
+                    if !editor.undo() {
+                        editor.display_status("Nothing to undo");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                Redo => {
+                    if !editor.redo() {
+                        editor.display_status("Nothing to redo");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                Replace => {
+                    let status_msg = find_and_replace(&mut editor, &mut stdin, &mut stdout,
+                                                      &mut search_options);
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                GoToStartOfBuffer => {
+                    editor.cursor_to_start_of_buffer();
+                },
+                GoToEndOfBuffer => {
+                    editor.cursor_to_end_of_buffer();
+                },
+                CenterView => {
+                    editor.center_view();
+                },
+                ScrollUp => {
+                    editor.scroll_view(editor::CursorDirection::Up);
+                },
+                ScrollDown => {
+                    editor.scroll_view(editor::CursorDirection::Down);
+                },
+                GoToLine => {
+                    let msg = "Go to line (line[:column], +N, -N or N%): ";
+                    if let Some(spec) = prompt(&mut editor, &mut stdin, &mut stdout, msg) {
+                        match goto::parse(&spec, editor.cursor_position(), editor.line_count()) {
+                            Ok((row, col)) => editor.jump_to(row, col),
+                            Err(msg) => {
+                                editor.display_status(msg);
+                                last_time_of_status = Instant::now();
+                            }
+                        }
+                    }
+                },
+                ToggleRegex => {
+                    search_options.regex = !search_options.regex;
+                    editor.display_status(format!("Search: {}", search_options.describe()));
+                    last_time_of_status = Instant::now();
+                },
+                ToggleCaseSensitive => {
+                    search_options.case_sensitive = !search_options.case_sensitive;
+                    editor.display_status(format!("Search: {}", search_options.describe()));
+                    last_time_of_status = Instant::now();
+                },
+                ToggleWholeWord => {
+                    search_options.whole_word = !search_options.whole_word;
+                    editor.display_status(format!("Search: {}", search_options.describe()));
+                    last_time_of_status = Instant::now();
+                },
+                BlockSelectUp => {
+                    editor.extend_block_selection(editor::CursorDirection::Up);
+                },
+                BlockSelectDown => {
+                    editor.extend_block_selection(editor::CursorDirection::Down);
+                },
+                BlockSelectLeft => {
+                    editor.extend_block_selection(editor::CursorDirection::Left);
+                },
+                BlockSelectRight => {
+                    editor.extend_block_selection(editor::CursorDirection::Right);
+                },
+                AddCursorAbove => {
+                    if editor.add_cursor(editor::CursorDirection::Up) {
+                        let count = editor.cursor_count();
+                        editor.display_status(format!("{} cursors", count));
+                    } else {
+                        editor.display_status("No line to add a cursor on");
+                    }
+                    last_time_of_status = Instant::now();
+                },
+                AddCursorBelow => {
+                    if editor.add_cursor(editor::CursorDirection::Down) {
+                        let count = editor.cursor_count();
+                        editor.display_status(format!("{} cursors", count));
+                    } else {
+                        editor.display_status("No line to add a cursor on");
+                    }
+                    last_time_of_status = Instant::now();
+                },
+                AddCursorAtNextOccurrence => {
+                    if editor.add_cursor_at_next_occurrence() {
+                        let count = editor.cursor_count();
+                        editor.display_status(format!("{} cursors", count));
+                    } else {
+                        editor.display_status("No other occurrence");
+                    }
+                    last_time_of_status = Instant::now();
+                },
+                Escape => {
+                    editor.clear_cursors();
+                },
                 Quit => break,
                 Char('\n') => {
                     editor.newline();
                 },
+                Char('\t') => {
+                    if editor.has_selection() {
+                        editor.indent_lines();
+                    } else {
+                        editor.insert_tab();
+                    }
+                },
+                DuplicateLines => {
+                    editor.duplicate();
+                },
+                MoveLinesUp => {
+                    editor.move_lines(editor::CursorDirection::Up);
+                },
+                MoveLinesDown => {
+                    editor.move_lines(editor::CursorDirection::Down);
+                },
+                JoinLines => {
+                    editor.join_lines();
+                },
+                DeleteLines => {
+                    editor.delete_lines();
+                },
+                SortLines => {
+                    let status_msg = match editor.sort_lines(false) {
+                        Some(_) => "Sorted lines".to_owned(),
+                        None => "Select the lines to sort first".to_owned(),
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                SortLinesUnique => {
+                    let status_msg = match editor.sort_lines(true) {
+                        Some(dropped) => format!("Sorted lines, dropping {} duplicate{}", dropped,
+                                                 if dropped == 1 { "" } else { "s" }),
+                        None => "Select the lines to sort first".to_owned(),
+                    };
+                    editor.display_status(status_msg);
+                    last_time_of_status = Instant::now();
+                },
+                ToggleComment => {
+                    if !editor.toggle_comment() {
+                        editor.display_status("No comment syntax for this file type");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                Reflow => {
+                    if !editor.reflow() {
+                        editor.display_status("No paragraph to reflow");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                ConvertCase(case) => {
+                    if !editor.convert_case(case) {
+                        editor.display_status("Nothing to convert");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                IncrementNumber(delta) => {
+                    if !editor.increment_number(delta) {
+                        editor.display_status("No number at the cursor");
+                        last_time_of_status = Instant::now();
+                    }
+                },
+                TransposeChars => {
+                    editor.transpose_chars();
+                },
+                TransposeWords => {
+                    editor.transpose_words();
+                },
+                Outdent => {
+                    editor.outdent_lines();
+                },
+                Complete => {
+                    if !editor.start_completion() {
+                        editor.display_status("No completions");
+                        last_time_of_status = Instant::now();
+                    }
+                },
                 Char(c) => {
                     editor.insert_char(c);
                 },
                 _ => {}
             }
+            if completing {
+                editor.update_completion();
+            }
         }
         if ShouldResizeWindow.compare_and_swap(true, false, Ordering::Relaxed) {
             let (screen_cols, screen_rows) = terminal_size()
@@ -161,9 +799,12 @@ fn main() {
         if Instant::now() - last_time_of_status > status_gap {
             editor.empty_status();
         }
-        render!(editor, stdout);
+        render!(editor, &mut stdout);
     }
 
-    write!(stdout, "{}{}{}", termion::cursor::Goto(1, 1),
-           termion::clear::All, termion::cursor::Show).unwrap();
+    write!(stdout, "{}{}{}{}", termion::cursor::Goto(1, 1),
+           termion::clear::All, termion::cursor::Show, keyboard::DISABLE_BRACKETED_PASTE).unwrap();
+    if mouse {
+        write!(stdout, "{}", keyboard::DISABLE_MOUSE).unwrap();
+    }
 }
@@ -0,0 +1,218 @@
+use regex;
+use regex::{Regex, RegexBuilder};
+
+/// How a search pattern is matched against the buffer.
+#[derive(Copy, Clone)]
+pub struct SearchOptions {
+    /// Whether the pattern is a regular expression rather than plain text
+    pub regex: bool,
+    /// Whether upper and lower case letters are told apart
+    pub case_sensitive: bool,
+    /// Whether matches must start and end on word boundaries
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    pub fn new() -> Self {
+        SearchOptions {
+            regex: false,
+            case_sensitive: true,
+            whole_word: false,
+        }
+    }
+
+    /// Returns a short description of the options for the search prompt.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![if self.regex { "regex" } else { "plain" }];
+        if !self.case_sensitive {
+            parts.push("ignore case");
+        }
+        if self.whole_word {
+            parts.push("whole word");
+        }
+        parts.join(", ")
+    }
+}
+
+/// A compiled search pattern.
+pub struct Search {
+    pattern: String,
+    regex: Regex,
+    /// Whether `$1` and `${name}` in replacements refer to capture groups
+    expand_captures: bool,
+}
+
+impl Search {
+    /// Compiles `pattern`, returning a description of what is wrong with it
+    /// if it isn't a valid regular expression.
+    pub fn new(pattern: &str, options: SearchOptions) -> Result<Search, String> {
+        let mut source = if options.regex {
+            pattern.to_owned()
+        } else {
+            regex::quote(pattern)
+        };
+        if options.whole_word {
+            source = format!(r"\b(?:{})\b", source);
+        }
+        RegexBuilder::new(&source)
+            .case_insensitive(!options.case_sensitive)
+            .multi_line(true)
+            .compile()
+            .map(|regex| Search {
+                pattern: pattern.to_owned(),
+                regex: regex,
+                expand_captures: options.regex,
+            })
+            .map_err(|e| e.to_string().lines().next().unwrap_or("").to_owned())
+    }
+
+    /// Returns the pattern as the user typed it.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns the byte range of the first match in `text` at or after byte
+    /// offset `start`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        self.regex.find_at(text, start)
+    }
+
+    /// Returns the text to put in place of the match starting at byte offset
+    /// `start` of `text`, expanding any capture group references in
+    /// `template`.
+    pub fn replacement(&self, text: &str, start: usize, template: &str) -> String {
+        if !self.expand_captures {
+            return template.to_owned();
+        }
+        // Searching the whole text from `start` lets anchors see what comes
+        // before the match, but `\b` and `\B` right at `start` only do when
+        // the search starts a character earlier
+        let before = text[..start].char_indices().next_back().map_or(start, |(i, _)| i);
+        let mut slots = vec![None; 2 * self.regex.captures_len()];
+        let found = [start, before].iter().any(|&from| {
+            self.regex.read_captures_at(&mut slots, text, from).map(|m| m.0) == Some(start)
+        });
+        if !found {
+            return template.to_owned();
+        }
+        expand(template, |name| {
+            let idx = match name.parse::<usize>() {
+                Ok(idx) => Some(idx),
+                Err(_) => self.regex.capture_names().position(|n| n == Some(name)),
+            };
+            match idx.map(|idx| (slots.get(2 * idx), slots.get(2 * idx + 1))) {
+                Some((Some(&Some(s)), Some(&Some(e)))) => Some(&text[s..e]),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Expands `$1`, `${1}`, `$name` and `${name}` in `template` with the groups
+/// `group` finds for a number or name. `$$` stands for a literal `$`.
+fn expand<'t, F: Fn(&str) -> Option<&'t str>>(template: &str, group: F) -> String {
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '$' {
+            expanded.push(ch);
+            continue;
+        }
+        let mut name = String::new();
+        match chars.peek().cloned() {
+            Some('$') => {
+                chars.next();
+                expanded.push('$');
+                continue;
+            },
+            Some('{') => {
+                chars.next();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    expanded.push_str("${");
+                    expanded.push_str(&name);
+                    continue;
+                }
+            },
+            Some(c) if c.is_alphanumeric() || c == '_' => {
+                while let Some(c) = chars.peek().cloned() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+            },
+            _ => {
+                expanded.push('$');
+                continue;
+            }
+        }
+        expanded.push_str(group(&name).unwrap_or(""));
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::{Captures, Regex};
+    use super::{Search, SearchOptions};
+
+    fn expand(template: &str, caps: &Captures) -> String {
+        super::expand(template, |name| match name.parse::<usize>() {
+            Ok(idx) => caps.at(idx),
+            Err(_) => caps.name(name),
+        })
+    }
+
+    fn regex_options() -> SearchOptions {
+        let mut options = SearchOptions::new();
+        options.regex = true;
+        options
+    }
+
+    #[test]
+    fn expands_groups() {
+        let regex = Regex::new(r"(?P<word>\w+) (\d+)").unwrap();
+        let caps = regex.captures("abc 42").unwrap();
+        assert_eq!(expand("$2-$1", &caps), "42-abc");
+        assert_eq!(expand("${word}s", &caps), "abcs");
+        assert_eq!(expand("$words", &caps), "");
+        assert_eq!(expand("${2}0", &caps), "420");
+        assert_eq!(expand("$$1 costs $", &caps), "$1 costs $");
+        assert_eq!(expand("${word", &caps), "${word");
+        assert_eq!(expand("$9", &caps), "");
+    }
+
+    #[test]
+    fn replacement_sees_text_before_the_match() {
+        let search = Search::new(r"\Bb(.)", regex_options()).unwrap();
+        let start = search.find_at("abc", 0).unwrap().0;
+        assert_eq!(search.replacement("abc", start, "[$1]"), "[c]");
+
+        let search = Search::new(r"^(\w)", regex_options()).unwrap();
+        assert_eq!(search.replacement("ab\ncd", 3, "<$1>"), "<c>");
+    }
+
+    #[test]
+    fn replacement_of_a_match_found_after_an_edit() {
+        // Scanning `acd` from the start matches `ac`, never one at `c`
+        let search = Search::new(r"(?P<first>\w)\w", regex_options()).unwrap();
+        assert_eq!(search.replacement("acd", 1, "[$1]"), "[c]");
+        assert_eq!(search.replacement("acd", 1, "[${first}]"), "[c]");
+        assert_eq!(search.replacement("acd", 2, "[$1]"), "[$1]");
+    }
+
+    #[test]
+    fn plain_replacements_are_literal() {
+        let search = Search::new("(a)", SearchOptions::new()).unwrap();
+        assert_eq!(search.replacement("x(a)", 1, "$1"), "$1");
+    }
+}
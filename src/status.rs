@@ -0,0 +1,173 @@
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const ELLIPSIS: char = '…';
+
+/// Expands the `{field}` placeholders in a status bar template using
+/// `lookup`. Unknown fields are left as they are, and a field expanding to
+/// nothing takes the space after it along with it.
+pub fn expand<F: Fn(&str) -> Option<String>>(template: &str, lookup: F) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..open]);
+        let field_len = match rest[open..].find('}') {
+            Some(len) => len,
+            None => {
+                // An unclosed brace is left as it is
+                rest = &rest[open..];
+                break;
+            },
+        };
+        let field = &rest[open+1..open+field_len];
+        rest = &rest[open+field_len+1..];
+        match lookup(field) {
+            Some(ref value) if value.is_empty() => {
+                if rest.starts_with(' ') {
+                    rest = &rest[1..];
+                }
+            },
+            Some(value) => expanded.push_str(&value),
+            None => {
+                expanded.push('{');
+                expanded.push_str(field);
+                expanded.push('}');
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Shortens `s` to at most `width` characters, ending it with an ellipsis if
+/// anything was cut off.
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_owned();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut truncated: String = s.chars().take(width - 1).collect();
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+/// Lays out the left- and right-aligned parts of the status bar in exactly
+/// `width` columns. The right part wins if there isn't room for both.
+pub fn layout(lhs: &str, rhs: &str, width: usize) -> String {
+    let rhs = truncate(rhs, width);
+    let rhs_len = rhs.chars().count();
+    // Keep a space between the two parts
+    let room = if rhs_len == 0 { width } else { width.saturating_sub(rhs_len + 1) };
+    let lhs = truncate(lhs, room);
+    let padding = width - lhs.chars().count() - rhs_len;
+    let mut bar = lhs;
+    bar.push_str(&" ".repeat(padding));
+    bar.push_str(&rhs);
+    bar
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    let mut contents = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => Some(contents.trim().to_owned()),
+        Err(_) => None,
+    }
+}
+
+/// Returns the git directory for the work tree at `dir`, if it is one.
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    // Worktrees and submodules have a file pointing at the real directory
+    match read_trimmed(&dot_git) {
+        Some(ref contents) if contents.starts_with("gitdir: ") => {
+            Some(dir.join(&contents["gitdir: ".len()..]))
+        },
+        _ => None,
+    }
+}
+
+/// Returns the branch checked out in the git repository containing
+/// `filename`, or the abbreviated commit if the HEAD is detached.
+pub fn git_branch(filename: &str) -> Option<String> {
+    let path = match fs::canonicalize(filename) {
+        Ok(path) => path,
+        Err(_) => return None,
+    };
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if let Some(git) = git_dir(d) {
+            return read_trimmed(&git.join("HEAD")).map(|head| {
+                let mut parts = head.splitn(2, "ref: refs/heads/");
+                match (parts.next(), parts.next()) {
+                    (Some(""), Some(branch)) => branch.to_owned(),
+                    _ => head.chars().take(7).collect(),
+                }
+            });
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use super::{expand, git_branch, layout};
+
+    fn lookup(field: &str) -> Option<String> {
+        match field {
+            "name" => Some("main.rs".to_owned()),
+            "empty" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands_fields() {
+        assert_eq!(expand("{name} - {empty} x", lookup), "main.rs - x");
+        assert_eq!(expand("{unknown} {name}", lookup), "{unknown} main.rs");
+        assert_eq!(expand("abc {foo", lookup), "abc {foo");
+        assert_eq!(expand("{name} {", lookup), "main.rs {");
+        assert_eq!(expand("", lookup), "");
+    }
+
+    #[test]
+    fn lays_out_both_sides() {
+        assert_eq!(layout("left", "right", 12), "left   right");
+        assert_eq!(layout("left", "", 6), "left  ");
+        assert_eq!(layout("a long left side", "rhs", 10), "a lon… rhs");
+    }
+
+    #[test]
+    fn lays_out_narrow_bars() {
+        assert_eq!(layout("left", "right", 0), "");
+        assert_eq!(layout("left", "right", 1), "…");
+        assert_eq!(layout("left", "right", 3), "ri…");
+        assert_eq!(layout("left", "right", 5), "right");
+        assert_eq!(layout("left", "right", 6), " right");
+    }
+
+    #[test]
+    fn reads_git_branch() {
+        let dir = env::temp_dir().join("mutxt-status-test");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        let file = dir.join("file.txt");
+        File::create(&file).unwrap();
+        let name = file.to_str().unwrap();
+
+        File::create(dir.join(".git/HEAD")).unwrap().write_all(b"ref: refs/heads/topic/x\n").unwrap();
+        assert_eq!(git_branch(name), Some("topic/x".to_owned()));
+        File::create(dir.join(".git/HEAD")).unwrap().write_all(b"0123456789abcdef\n").unwrap();
+        assert_eq!(git_branch(name), Some("0123456".to_owned()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use regex::Regex;
+
+bitflags! {
+    pub flags HighlightParams: u8 {
+        const HighlightStrings = (1 << 0),
+        const HighlightNumbers = (1 << 1),
+    }
+}
+
+/// The characters typed in pairs when auto-pairing is on
+const AUTO_PAIRS: [(char, char); 5] = [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+/// Returns the pairs of characters to type together in a file of type
+/// `filetype`. Apostrophes are left out where they are mostly used on their
+/// own: for lifetimes in Rust, and in the words of prose.
+pub fn auto_pairs(filetype: Option<&str>) -> Vec<(char, char)> {
+    let lone_apostrophes = match filetype {
+        Some("rust") | Some("markdown") | Some("text") | None => true,
+        _ => false,
+    };
+    AUTO_PAIRS.iter()
+        .cloned()
+        .filter(|&(open, _)| !(lone_apostrophes && open == '\''))
+        .collect()
+}
+
+/// A type defining elements of syntax
+pub struct SyntaxHighlightRule {
+    /// The name of the filetype
+    pub name: String,
+    /// The keywords of the language
+    pub keywords: HashSet<String>,
+    /// The start character sequence for a single-line comment
+    pub single_line_comment_start: HashSet<String>,
+    /// The start character sequence for a multi-line comment
+    pub multi_line_comment_start: String,
+    /// The end character sequence for a multi-line comment
+    pub multi_line_comment_end: String,
+    /// Flags specifying what elements of syntax should be highlighted
+    pub params: HighlightParams,
+    /// The tokens that, ending a line, indent the next line one level further
+    pub indent_after: Vec<String>,
+    /// A regular expression matching the lines that start a function
+    /// definition, if there is one
+    pub function_pattern: Option<Regex>,
+}
+
+fn strings(items: &[&str]) -> HashSet<String> {
+    items.iter().map(|s| (*s).to_owned()).collect()
+}
+
+impl SyntaxHighlightRule {
+    fn new(name: &str, keywords: &[&str], single_line_comment_start: &[&str],
+           multi_line_comment: (&str, &str), params: HighlightParams,
+           indent_after: &[&str], function_pattern: &str) -> Self {
+        SyntaxHighlightRule {
+            name: name.to_owned(),
+            keywords: strings(keywords),
+            single_line_comment_start: strings(single_line_comment_start),
+            multi_line_comment_start: multi_line_comment.0.to_owned(),
+            multi_line_comment_end: multi_line_comment.1.to_owned(),
+            params: params,
+            indent_after: indent_after.iter().map(|s| (*s).to_owned()).collect(),
+            function_pattern: if function_pattern.is_empty() { None } else { Regex::new(function_pattern).ok() },
+        }
+    }
+
+    /// Picks the rule for a file based on its name, if there is one.
+    pub fn for_filename(filename: &str) -> Option<Self> {
+        let path = Path::new(filename);
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let code = HighlightStrings | HighlightNumbers;
+        let brackets = &["{", "(", "["];
+
+        let rule = match (name, extension) {
+            (_, "rs") => SyntaxHighlightRule::new(
+                "rust",
+                &["as", "break", "const", "continue", "crate", "else", "enum", "extern",
+                  "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+                  "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+                  "super", "trait", "true", "type", "unsafe", "use", "where", "while"],
+                &["//"], ("/*", "*/"), code, brackets,
+                r#"^\s*(pub(\([^)]*\))?\s+)?((const|unsafe|async|extern(\s+"[^"]*")?)\s+)*fn\s"#),
+            (_, "c") | (_, "h") => SyntaxHighlightRule::new(
+                "c",
+                &["auto", "break", "case", "char", "const", "continue", "default", "do",
+                  "double", "else", "enum", "extern", "float", "for", "goto", "if", "int",
+                  "long", "register", "return", "short", "signed", "sizeof", "static",
+                  "struct", "switch", "typedef", "union", "unsigned", "void", "volatile",
+                  "while"],
+                &["//"], ("/*", "*/"), code, brackets,
+                r"^[A-Za-z_][\w\s\*]*\b\w+\s*\([^;]*$"),
+            (_, "cpp") | (_, "cc") | (_, "cxx") | (_, "hpp") => SyntaxHighlightRule::new(
+                "c++",
+                &["auto", "bool", "break", "case", "catch", "char", "class", "const",
+                  "continue", "default", "delete", "do", "double", "else", "enum", "false",
+                  "float", "for", "if", "int", "long", "namespace", "new", "private",
+                  "protected", "public", "return", "short", "static", "struct", "switch",
+                  "template", "this", "throw", "true", "try", "typename", "using",
+                  "virtual", "void", "while"],
+                &["//"], ("/*", "*/"), code, brackets,
+                r"^[A-Za-z_][\w\s\*&:<>,]*\b[\w:~]+\s*\([^;]*$"),
+            (_, "go") => SyntaxHighlightRule::new(
+                "go",
+                &["break", "case", "chan", "const", "continue", "default", "defer", "else",
+                  "fallthrough", "for", "func", "go", "goto", "if", "import", "interface",
+                  "map", "package", "range", "return", "select", "struct", "switch", "type",
+                  "var"],
+                &["//"], ("/*", "*/"), code, brackets, r"^func\s"),
+            (_, "js") | (_, "ts") => SyntaxHighlightRule::new(
+                "javascript",
+                &["break", "case", "catch", "class", "const", "continue", "default",
+                  "delete", "do", "else", "export", "false", "finally", "for", "function",
+                  "if", "import", "in", "instanceof", "let", "new", "null", "return",
+                  "switch", "this", "throw", "true", "try", "typeof", "var", "void",
+                  "while"],
+                &["//"], ("/*", "*/"), code, brackets,
+                r"^\s*(export\s+)?(async\s+)?function\b"),
+            (_, "py") => SyntaxHighlightRule::new(
+                "python",
+                &["and", "as", "assert", "break", "class", "continue", "def", "del", "elif",
+                  "else", "except", "False", "finally", "for", "from", "global", "if",
+                  "import", "in", "is", "lambda", "None", "not", "or", "pass", "raise",
+                  "return", "True", "try", "while", "with", "yield"],
+                &["#"], ("", ""), code, &[":", "{", "(", "["],
+                r"^\s*(async\s+)?def\s"),
+            (_, "sh") | (_, "bash") | (".bashrc", _) | (".profile", _) => SyntaxHighlightRule::new(
+                "shell",
+                &["case", "do", "done", "elif", "else", "esac", "fi", "for", "function",
+                  "if", "in", "then", "until", "while"],
+                &["#"], ("", ""), code, &["then", "do", "else", "{", "("],
+                r"^\s*(function\s+[\w-]+|[\w-]+\s*\(\s*\))"),
+            ("Makefile", _) | ("makefile", _) | (_, "mk") => SyntaxHighlightRule::new(
+                "make", &[], &["#"], ("", ""), HighlightParams::empty(), &[], ""),
+            (_, "toml") | (".mutxtrc", _) => SyntaxHighlightRule::new(
+                "toml", &["true", "false"], &["#"], ("", ""), code, &["[", "{"], ""),
+            (_, "css") => SyntaxHighlightRule::new(
+                "css", &[], &[], ("/*", "*/"), HighlightNumbers, brackets, ""),
+            (_, "html") | (_, "xml") => SyntaxHighlightRule::new(
+                "html", &[], &[], ("<!--", "-->"), HighlightStrings, &[], ""),
+            (_, "md") | (_, "markdown") => SyntaxHighlightRule::new(
+                "markdown", &[], &[], ("", ""), HighlightParams::empty(), &[], ""),
+            (_, "txt") => SyntaxHighlightRule::new(
+                "text", &[], &[], ("", ""), HighlightParams::empty(), &[], ""),
+            _ => return None,
+        };
+        Some(rule)
+    }
+}
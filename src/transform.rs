@@ -0,0 +1,231 @@
+//! Text transformations: changing the case of words and identifiers, and
+//! stepping numbers and dates.
+
+use regex::Regex;
+use words;
+
+/// The cases words and identifiers can be converted to.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Case {
+    Upper,
+    Lower,
+    Title,
+    Snake,
+    Camel,
+    Kebab,
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect(),
+        None => String::new(),
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Converts one identifier to snake, camel or kebab case, splitting it into
+/// parts at underscores, hyphens and camel humps. Leading and trailing
+/// underscores and hyphens are kept.
+fn convert_identifier(identifier: &[char], case: Case) -> String {
+    let lead = identifier.iter().take_while(|&&c| c == '_' || c == '-').count();
+    let trail = identifier[lead..].iter().rev().take_while(|&&c| c == '_' || c == '-').count();
+    let body = &identifier[lead..identifier.len() - trail];
+    let parts: Vec<String> = words::word_spans(body, true).iter()
+        .map(|&(start, end)| body[start..end].iter().cloned().collect())
+        .collect();
+    let converted = match case {
+        Case::Snake => parts.iter().map(|p| p.to_lowercase()).collect::<Vec<_>>().join("_"),
+        Case::Kebab => parts.iter().map(|p| p.to_lowercase()).collect::<Vec<_>>().join("-"),
+        _ => {
+            parts.iter().enumerate()
+                .map(|(i, p)| if i == 0 { p.to_lowercase() } else { capitalize(p) })
+                .collect()
+        },
+    };
+    let mut result: String = identifier[..lead].iter().cloned().collect();
+    result.push_str(&converted);
+    result.extend(identifier[identifier.len() - trail..].iter().cloned());
+    result
+}
+
+/// Converts `text` to `case`. Title case capitalizes each word, while snake,
+/// camel and kebab case convert each identifier in the text.
+pub fn convert_case(text: &str, case: Case) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    match case {
+        Case::Upper => text.to_uppercase(),
+        Case::Lower => text.to_lowercase(),
+        Case::Title => {
+            let mut result = String::new();
+            let mut last = 0;
+            for (start, end) in words::word_spans(&chars, false) {
+                result.extend(chars[last..start].iter().cloned());
+                result.push_str(&capitalize(&chars[start..end].iter().cloned().collect::<String>()));
+                last = end;
+            }
+            result.extend(chars[last..].iter().cloned());
+            result
+        },
+        _ => {
+            let mut result = String::new();
+            let mut i = 0;
+            while i < chars.len() {
+                let start = i;
+                while i < chars.len() && is_identifier_char(chars[i]) {
+                    i += 1;
+                }
+                if i > start {
+                    result.push_str(&convert_identifier(&chars[start..i], case));
+                } else {
+                    result.push(chars[i]);
+                    i += 1;
+                }
+            }
+            result
+        },
+    }
+}
+
+/// Returns the number of days from 1970-01-01 to the given date in the
+/// proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the date `days` days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Adds `delta` days to a `YYYY-MM-DD` date, returning `None` if it isn't a
+/// real date.
+fn step_date(date: &str, delta: i64) -> Option<String> {
+    let parts: Vec<i64> = date.split('-').filter_map(|p| p.parse().ok()).collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let days = days_from_civil(parts[0], parts[1], parts[2]);
+    if civil_from_days(days) != (parts[0], parts[1], parts[2]) {
+        return None;
+    }
+    let (year, month, day) = civil_from_days(days + delta);
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// Adds `delta` to a hexadecimal number with a `0x` prefix, keeping the case
+/// of its digits and padding it to at least as many digits as before.
+fn step_hex(number: &str, delta: i64) -> Option<String> {
+    let digits = &number[2..];
+    let value = match u64::from_str_radix(digits, 16) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let stepped = value.wrapping_add(delta as u64);
+    let upper = digits.chars().any(|c| c.is_uppercase());
+    let formatted = if upper {
+        format!("{:01$X}", stepped, digits.len())
+    } else {
+        format!("{:01$x}", stepped, digits.len())
+    };
+    Some(format!("{}{}", &number[..2], formatted))
+}
+
+/// Adds `delta` to a decimal number, keeping any leading zeros it was padded
+/// with.
+fn step_decimal(number: &str, delta: i64) -> Option<String> {
+    let value: i64 = match number.parse() {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let stepped = match value.checked_add(delta) {
+        Some(stepped) => stepped,
+        None => return None,
+    };
+    let digits = number.trim_matches('-');
+    if digits.len() > 1 && digits.starts_with('0') {
+        let sign = if stepped < 0 { "-" } else { "" };
+        Some(format!("{}{:02$}", sign, stepped.abs(), digits.len()))
+    } else {
+        Some(stepped.to_string())
+    }
+}
+
+/// Finds the number or `YYYY-MM-DD` date under or after column `col` of
+/// `line` and adds `delta` to it, or that many days to a date. Returns the
+/// columns the number spans and its new text.
+pub fn increment(line: &str, col: usize, delta: i64) -> Option<(usize, usize, String)> {
+    let pattern = Regex::new(r"\d{4}-\d{2}-\d{2}|0[xX][0-9a-fA-F]+|-?\d+").unwrap();
+    let col_of = |offset: usize| line[..offset].chars().count();
+    for (start, end) in pattern.find_iter(line) {
+        if col_of(end) <= col {
+            continue;
+        }
+        let number = &line[start..end];
+        let stepped = if number.len() == 10 && number.as_bytes()[4] == b'-' {
+            step_date(number, delta)
+        } else if number.starts_with("0x") || number.starts_with("0X") {
+            step_hex(number, delta)
+        } else {
+            step_decimal(number, delta)
+        };
+        return stepped.map(|text| (col_of(start), col_of(end), text));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_case, increment, Case};
+
+    #[test]
+    fn converts_case() {
+        assert_eq!(convert_case("hello World", Case::Upper), "HELLO WORLD");
+        assert_eq!(convert_case("Hello WORLD", Case::Lower), "hello world");
+        assert_eq!(convert_case("the qUICK fox", Case::Title), "The Quick Fox");
+    }
+
+    #[test]
+    fn converts_identifiers() {
+        assert_eq!(convert_case("fooBarBaz", Case::Snake), "foo_bar_baz");
+        assert_eq!(convert_case("foo_bar baz-qux", Case::Camel), "fooBar bazQux");
+        assert_eq!(convert_case("HTTPServer", Case::Kebab), "http-server");
+        assert_eq!(convert_case("__init_value__", Case::Camel), "__initValue__");
+    }
+
+    #[test]
+    fn increments_numbers() {
+        assert_eq!(increment("x = 41;", 0, 1), Some((4, 6, "42".to_owned())));
+        assert_eq!(increment("a -1 b", 3, 2), Some((2, 4, "1".to_owned())));
+        assert_eq!(increment("v007", 0, 1), Some((1, 4, "008".to_owned())));
+        assert_eq!(increment("0xff 0x0F", 0, 1), Some((0, 4, "0x100".to_owned())));
+        assert_eq!(increment("0xff 0x0F", 5, 1), Some((5, 9, "0x10".to_owned())));
+        assert_eq!(increment("12 34", 2, 1), Some((3, 5, "35".to_owned())));
+        assert_eq!(increment("none", 0, 1), None);
+    }
+
+    #[test]
+    fn steps_dates() {
+        assert_eq!(increment("due 2024-02-28", 0, 1), Some((4, 14, "2024-02-29".to_owned())));
+        assert_eq!(increment("2023-12-31", 5, 1), Some((0, 10, "2024-01-01".to_owned())));
+        assert_eq!(increment("2024-03-01", 0, -1), Some((0, 10, "2024-02-29".to_owned())));
+        assert_eq!(increment("2023-02-30", 0, 1), None);
+    }
+}
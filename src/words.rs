@@ -0,0 +1,181 @@
+//! Word boundaries for cursor motions.
+//!
+//! These loosely follow the Unicode word segmentation rules (UAX #29): letters,
+//! digits and combining marks make up words, an apostrophe between letters
+//! (as in "don't") and a point or comma between digits (as in "3.14") don't
+//! split a word, and each CJK ideograph is a word of its own. Unlike UAX #29,
+//! a full stop or colon between letters does split words, so `foo.bar` and
+//! `std::io` are two words each, and underscores join words.
+//!
+//! Subwords go further, splitting words at underscores and at the humps of
+//! camelCase and PascalCase names.
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Punctuation,
+    Word,
+    Ideograph,
+}
+
+/// Combining characters, which belong with the character before them
+const EXTEND_RANGES: [(u32, u32); 7] = [
+    (0x0300, 0x036F), (0x1AB0, 0x1AFF), (0x1DC0, 0x1DFF), (0x20D0, 0x20FF),
+    (0xFE00, 0xFE0F), (0xFE20, 0xFE2F), (0x200D, 0x200D),
+];
+
+/// CJK ideographs, each of which is a word of its own
+const IDEOGRAPH_RANGES: [(u32, u32); 4] = [
+    (0x3400, 0x4DBF), (0x4E00, 0x9FFF), (0xF900, 0xFAFF), (0x20000, 0x2FA1F),
+];
+
+fn in_ranges(c: char, ranges: &[(u32, u32)]) -> bool {
+    ranges.iter().any(|&(first, last)| first <= c as u32 && c as u32 <= last)
+}
+
+fn is_extend(c: char) -> bool {
+    in_ranges(c, &EXTEND_RANGES)
+}
+
+fn is_ideograph(c: char) -> bool {
+    in_ranges(c, &IDEOGRAPH_RANGES)
+}
+
+fn class_at(chars: &[char], i: usize) -> CharClass {
+    let c = chars[i];
+    if is_ideograph(c) {
+        return CharClass::Ideograph;
+    }
+    if c.is_alphanumeric() || c == '_' || is_extend(c) {
+        return CharClass::Word;
+    }
+    let between = |f: fn(char) -> bool| {
+        i > 0 && i + 1 < chars.len() && f(chars[i - 1]) && f(chars[i + 1])
+    };
+    match c {
+        '\'' | '\u{2019}' if between(char::is_alphabetic) => CharClass::Word,
+        '.' | ',' if between(char::is_numeric) => CharClass::Word,
+        c if c.is_whitespace() => CharClass::Space,
+        _ => CharClass::Punctuation,
+    }
+}
+
+/// Splits the word between `start` and `end` into subwords.
+fn subword_spans(chars: &[char], start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut current: Option<usize> = None;
+    for i in start..end {
+        let c = chars[i];
+        if c == '_' {
+            if let Some(s) = current.take() {
+                spans.push((s, i));
+            }
+            continue;
+        }
+        let s = match current {
+            Some(s) => s,
+            None => {
+                current = Some(i);
+                continue;
+            }
+        };
+        let prev = chars[i - 1];
+        let next_is_lower = i + 1 < end && chars[i + 1].is_lowercase();
+        // A hump starts at an upper case letter after a lower case letter or
+        // digit, as in "camelCase", or at the last of a run of upper case
+        // letters followed by a lower case one, as in "HTTPServer"
+        let hump = c.is_uppercase() &&
+            (prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower));
+        if hump {
+            spans.push((s, i));
+            current = Some(i);
+        }
+    }
+    if let Some(s) = current {
+        spans.push((s, end));
+    }
+    spans
+}
+
+/// Returns the start and end columns of the words in a line, or of the
+/// subwords if `subwords` is set. Whitespace and punctuation aren't part of
+/// any word.
+pub fn word_spans(chars: &[char], subwords: bool) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match class_at(chars, i) {
+            CharClass::Word => {
+                while i < chars.len() && class_at(chars, i) == CharClass::Word {
+                    i += 1;
+                }
+                if subwords {
+                    spans.extend(subword_spans(chars, start, i));
+                } else {
+                    spans.push((start, i));
+                }
+            },
+            CharClass::Ideograph => {
+                i += 1;
+                while i < chars.len() && is_extend(chars[i]) {
+                    i += 1;
+                }
+                spans.push((start, i));
+            },
+            CharClass::Space | CharClass::Punctuation => i += 1,
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::word_spans;
+
+    fn words(line: &str, subwords: bool) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        word_spans(&chars, subwords).iter()
+            .map(|&(start, end)| chars[start..end].iter().cloned().collect())
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_whitespace_and_punctuation() {
+        assert_eq!(words("let x = foo.bar(std::io);", false),
+                   vec!["let", "x", "foo", "bar", "std", "io"]);
+        assert_eq!(words("  --  ", false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keeps_underscores_in_words() {
+        assert_eq!(words("snake_case _private", false), vec!["snake_case", "_private"]);
+    }
+
+    #[test]
+    fn joins_apostrophes_and_decimal_points() {
+        assert_eq!(words("don't 'quote' 3.14 1,000 a.b", false),
+                   vec!["don't", "quote", "3.14", "1,000", "a", "b"]);
+    }
+
+    #[test]
+    fn handles_non_ascii_letters() {
+        assert_eq!(words("naïve café ünïcödé", false), vec!["naïve", "café", "ünïcödé"]);
+        // "e" followed by a combining acute accent
+        assert_eq!(words("cafe\u{301} ok", false), vec!["cafe\u{301}", "ok"]);
+        assert_eq!(words("Ελληνικά кириллица", false), vec!["Ελληνικά", "кириллица"]);
+    }
+
+    #[test]
+    fn splits_ideographs() {
+        assert_eq!(words("漢字 abc", false), vec!["漢", "字", "abc"]);
+    }
+
+    #[test]
+    fn splits_subwords() {
+        assert_eq!(words("snake_case_name", true), vec!["snake", "case", "name"]);
+        assert_eq!(words("camelCaseName", true), vec!["camel", "Case", "Name"]);
+        assert_eq!(words("HTTPServer utf8Decoder", true), vec!["HTTP", "Server", "utf8", "Decoder"]);
+        assert_eq!(words("__init__ ALL_CAPS", true), vec!["init", "ALL", "CAPS"]);
+    }
+}